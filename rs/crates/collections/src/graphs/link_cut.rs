@@ -0,0 +1,232 @@
+use crate::trees::monoid::Monoid;
+
+/// Link-cut tree over a forest of `n` vertices, each holding a monoid value.
+///
+/// Every preferred path is kept in a splay tree keyed by depth. Reversal is
+/// lazy, and each node caches the path aggregate in both directions, so
+/// `path_query` also works for non-commutative monoids.
+pub struct LinkCutTree<M: Monoid> {
+    nodes: Vec<LctNode<M::T>>,
+}
+
+struct LctNode<T> {
+    ch: [Option<usize>; 2],
+    par: Option<usize>,
+    rev: bool,
+    val: T,
+    agg: T,
+    rev_agg: T,
+}
+
+impl<M: Monoid> LinkCutTree<M> {
+    /// Creates `n` isolated vertices with identity values.
+    pub fn new(n: usize) -> Self {
+        Self::from_values((0..n).map(|_| M::identity()).collect())
+    }
+
+    pub fn from_values(vals: Vec<M::T>) -> Self {
+        let nodes = vals
+            .into_iter()
+            .map(|val| LctNode {
+                ch: [None, None],
+                par: None,
+                rev: false,
+                agg: val.clone(),
+                rev_agg: val.clone(),
+                val,
+            })
+            .collect();
+        Self { nodes }
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn get(&self, x: usize) -> &M::T {
+        &self.nodes[x].val
+    }
+
+    pub fn set(&mut self, x: usize, val: M::T) {
+        self.access(x);
+        self.nodes[x].val = val;
+        self.pull(x);
+    }
+
+    /// Adds the edge `u - v`, returns `false` if they are already connected.
+    pub fn link(&mut self, u: usize, v: usize) -> bool {
+        if self.connected(u, v) {
+            return false;
+        }
+        self.evert(u);
+        self.nodes[u].par = Some(v);
+        true
+    }
+
+    /// Removes the edge `u - v`, returns `false` if there is no such edge.
+    pub fn cut(&mut self, u: usize, v: usize) -> bool {
+        if u == v {
+            return false;
+        }
+        self.evert(u);
+        self.access(v);
+        // the path u..v is exactly [u, v] iff they are adjacent
+        if self.nodes[v].ch[0] != Some(u) || self.nodes[u].ch[1].is_some() {
+            return false;
+        }
+        self.nodes[v].ch[0] = None;
+        self.nodes[u].par = None;
+        self.pull(v);
+        true
+    }
+
+    pub fn connected(&mut self, u: usize, v: usize) -> bool {
+        self.find_root(u) == self.find_root(v)
+    }
+
+    /// Returns the root of the tree containing `x`.
+    pub fn find_root(&mut self, x: usize) -> usize {
+        self.access(x);
+        let mut r = x;
+        loop {
+            self.push(r);
+            match self.nodes[r].ch[0] {
+                Some(l) => r = l,
+                None => break,
+            }
+        }
+        self.splay(r);
+        r
+    }
+
+    /// Makes `x` the root of its tree.
+    pub fn evert(&mut self, x: usize) {
+        self.access(x);
+        self.toggle(x);
+    }
+
+    /// Lowest common ancestor under the current rooting, `None` if the
+    /// vertices are in different trees.
+    pub fn lca(&mut self, u: usize, v: usize) -> Option<usize> {
+        if !self.connected(u, v) {
+            return None;
+        }
+        self.access(u);
+        Some(self.access(v))
+    }
+
+    /// Folds the values along the path from `u` to `v`, in that order.
+    pub fn path_query(&mut self, u: usize, v: usize) -> Option<M::T> {
+        if !self.connected(u, v) {
+            return None;
+        }
+        self.evert(u);
+        self.access(v);
+        Some(self.nodes[v].agg.clone())
+    }
+
+    fn is_root(&self, x: usize) -> bool {
+        match self.nodes[x].par {
+            Some(p) => !self.nodes[p].ch.contains(&Some(x)),
+            None => true,
+        }
+    }
+
+    fn toggle(&mut self, x: usize) {
+        let n = &mut self.nodes[x];
+        n.ch.swap(0, 1);
+        std::mem::swap(&mut n.agg, &mut n.rev_agg);
+        n.rev ^= true;
+    }
+
+    fn push(&mut self, x: usize) {
+        if self.nodes[x].rev {
+            self.nodes[x].rev = false;
+            for c in self.nodes[x].ch.into_iter().flatten() {
+                self.toggle(c);
+            }
+        }
+    }
+
+    fn pull(&mut self, x: usize) {
+        let id = M::identity();
+        let [l, r] = self.nodes[x].ch;
+        let agg = |c: Option<usize>| c.map_or(&id, |c| &self.nodes[c].agg);
+        let rev_agg = |c: Option<usize>| c.map_or(&id, |c| &self.nodes[c].rev_agg);
+        let val = &self.nodes[x].val;
+        let a = M::op(&M::op(agg(l), val), agg(r));
+        let b = M::op(&M::op(rev_agg(r), val), rev_agg(l));
+        self.nodes[x].agg = a;
+        self.nodes[x].rev_agg = b;
+    }
+
+    fn rotate(&mut self, x: usize) {
+        let p = self.nodes[x].par.expect("rotated node has a parent");
+        let g = self.nodes[p].par;
+        let d = (self.nodes[p].ch[1] == Some(x)) as usize;
+
+        if !self.is_root(p) {
+            let g = g.expect("non-root has a parent");
+            let gd = (self.nodes[g].ch[1] == Some(p)) as usize;
+            self.nodes[g].ch[gd] = Some(x);
+        }
+        self.nodes[x].par = g;
+
+        let b = self.nodes[x].ch[d ^ 1];
+        self.nodes[p].ch[d] = b;
+        if let Some(b) = b {
+            self.nodes[b].par = Some(p);
+        }
+        self.nodes[x].ch[d ^ 1] = Some(p);
+        self.nodes[p].par = Some(x);
+
+        self.pull(p);
+        self.pull(x);
+    }
+
+    fn splay(&mut self, x: usize) {
+        // push pending reversals from the top of the splay tree down
+        let mut path = vec![x];
+        let mut y = x;
+        while !self.is_root(y) {
+            y = self.nodes[y].par.expect("non-root has a parent");
+            path.push(y);
+        }
+        for &y in path.iter().rev() {
+            self.push(y);
+        }
+
+        while !self.is_root(x) {
+            let p = self.nodes[x].par.expect("non-root has a parent");
+            if !self.is_root(p) {
+                let g = self.nodes[p].par.expect("non-root has a parent");
+                let zig_zig = (self.nodes[g].ch[1] == Some(p)) == (self.nodes[p].ch[1] == Some(x));
+                self.rotate(if zig_zig { p } else { x });
+            }
+            self.rotate(x);
+        }
+    }
+
+    /// Makes the root-to-`x` path preferred and splays `x` to the top.
+    /// Returns the last path-parent crossed, which is the LCA when called
+    /// right after accessing another vertex.
+    fn access(&mut self, x: usize) -> usize {
+        let mut last = None;
+        let mut y = Some(x);
+        let mut ret = x;
+        while let Some(v) = y {
+            self.splay(v);
+            self.nodes[v].ch[1] = last;
+            self.pull(v);
+            ret = v;
+            last = Some(v);
+            y = self.nodes[v].par;
+        }
+        self.splay(x);
+        ret
+    }
+}
@@ -0,0 +1,62 @@
+use std::{error::Error, fmt};
+
+/// A broken structural invariant, as reported by the `validate` methods.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvariantError {
+    /// Keys are not strictly increasing in order.
+    Order,
+    /// A cached subtree size or length disagrees with the actual count.
+    Size { expected: usize, found: usize },
+    /// The root of a red-black tree is red.
+    RedRoot,
+    /// A red link leans right in a left-leaning red-black tree.
+    RedRightLink,
+    /// Two red links in a row.
+    DoubleRed,
+    /// Paths to the leaves cross different numbers of black links.
+    BlackHeight,
+    /// A child has a higher priority than its parent.
+    Priority,
+    /// A child is greater than its parent in a max-heap.
+    HeapOrder,
+    /// A `dist` is wrong, or a right child is further from a leaf than the left one.
+    Dist,
+    /// A skip list level is not linked consistently with the level below it.
+    Level { level: usize },
+    /// A skip list span disagrees with the distance its link covers.
+    Span { level: usize },
+}
+
+use InvariantError::*;
+
+impl fmt::Display for InvariantError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Order => write!(f, "keys out of order"),
+            Size { expected, found } => {
+                write!(f, "size mismatch: expected {}, found {}", expected, found)
+            }
+            RedRoot => write!(f, "red root"),
+            RedRightLink => write!(f, "right-leaning red link"),
+            DoubleRed => write!(f, "two consecutive red links"),
+            BlackHeight => write!(f, "unbalanced black height"),
+            Priority => write!(f, "child priority above its parent"),
+            HeapOrder => write!(f, "child greater than its parent"),
+            Dist => write!(f, "wrong leftist dist"),
+            Level { level } => write!(f, "inconsistent links on level {}", level),
+            Span { level } => write!(f, "wrong span on level {}", level),
+        }
+    }
+}
+
+impl Error for InvariantError {}
+
+/// Validates `$s` after a mutation when the `debug-invariants` feature is on.
+macro_rules! debug_invariants {
+    ($s: expr) => {
+        #[cfg(feature = "debug-invariants")]
+        if let Err(e) = $s.validate() {
+            panic!("invariant violated: {}", e);
+        }
+    };
+}
@@ -1,38 +1,60 @@
 #![allow(unused_imports)]
 
+#[macro_use]
+pub mod invariant;
+
+#[macro_use]
 pub mod trees {
     pub mod binary_tree;
     pub mod bitree;
+    pub mod lazy_segment_tree;
     pub mod leftist_tree;
+    pub mod monoid;
     pub mod rbtree;
     pub mod segment_tree;
+    pub mod segment_tree_beats;
+    pub mod sparse_segment_tree;
     pub mod splay_tree;
     pub mod treap;
+    pub mod treap_seq;
 }
 
 pub mod lists {
+    pub mod concurrent_skip_list;
     pub mod linkedlist;
+    pub mod memtable;
     pub mod skip_list;
 }
 
 pub mod graphs {
     pub mod dsu;
+    pub mod link_cut;
     pub mod tarjan;
 }
 
 pub mod misc {
     pub mod sparse_table;
     pub mod median_finder;
+    pub mod rng;
     pub mod stream;
 }
 
+pub use invariant::InvariantError;
+pub use graphs::link_cut::LinkCutTree;
 pub use trees::bitree::BIT;
+pub use trees::lazy_segment_tree::LazySegTree;
 pub use trees::leftist_tree::LeftistTree;
 pub use trees::rbtree::RBTreeMap;
 pub use trees::segment_tree::{PstSegTree, SegTree};
-pub use lists::skip_list::SkipListSet;
+pub use trees::segment_tree_beats::SegTreeBeats;
+pub use trees::sparse_segment_tree::{SegForest, SparseSegTree};
+pub use lists::concurrent_skip_list::ConcurrentSkipListMap;
+pub use lists::memtable::MemTable;
+pub use lists::skip_list::{SkipListConfig, SkipListSet};
 pub use trees::splay_tree::SplayTreeMap;
-pub use trees::treap::TreapMap;
+pub use trees::monoid::Monoid;
+pub use trees::treap::{PstTreapMap, TreapMap};
+pub use trees::treap_seq::TreapSeq;
 
 #[allow(unused_macros)]
 macro_rules! timeit {
@@ -81,6 +103,28 @@ macro_rules! test_map {
     }};
 }
 
+#[allow(unused_macros)]
+macro_rules! test_entry {
+    ($t: tt) => {{
+        let mut s = $t::new();
+        let mut b = BTreeMap::new();
+
+        for i in 0..5000 {
+            let k = rand::random::<i32>() % 500;
+            *s.entry(k).or_insert(0) += 1;
+            *b.entry(k).or_insert(0) += 1;
+
+            s.entry(k + 1000).and_modify(|v| *v *= 2).or_insert_with(|| i);
+            b.entry(k + 1000).and_modify(|v| *v *= 2).or_insert_with(|| i);
+        }
+
+        for k in -1500..1500 {
+            assert_eq!(s.get(&k), b.get(&k));
+        }
+        assert_eq!(s.len(), b.len());
+    }};
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -91,11 +135,17 @@ mod tests {
         collections::{BTreeMap, BTreeSet, BinaryHeap},
         time,
     };
+    use crate::trees::monoid::{Affine, Gcd, Max, Min, Sum, Xor};
     use crate::trees::treap::TreapSet;
 
+    /// Benchmark sizes, shrunk when every mutation is validated.
+    fn scaled(n: usize) -> usize {
+        if cfg!(feature = "debug-invariants") { n.min(1000) } else { n }
+    }
+
     #[test]
     fn test_leftist_tree() {
-        let n = 10000;
+        let n = scaled(10000);
         let mut heap = LeftistTree::new();
         let mut rng = rand::thread_rng();
 
@@ -145,11 +195,435 @@ mod tests {
         assert_eq!(seg.query_nth(0, 10, 100), None);
 
         let v: Vec<_> = (0..100).collect();
-        let mut seg = SegTree::from(v, Box::new(|x: &i32, y: &i32| *x.max(y)));
+        let mut seg = SegTree::<Max<i32>>::from(v);
+
+        seg.set(50, 1);
+        assert_eq!(seg.query(45..50), 49);
+        assert_eq!(seg.query(45..=50), 49);
+        assert_eq!(seg.query(50..51), 1);
+        assert_eq!(seg.query(7..7), i32::MIN);
+        assert_eq!(*seg.get(50), 1);
+
+        let mut rng = rand::rng();
+        let mut v: Vec<u64> = (0..scaled(300)).map(|_| rng.random_range(0..1000)).collect();
+        let mut sum: SegTree<Sum<u64>> = v.iter().copied().collect();
+        let mut gcd = SegTree::<Gcd<u64>>::from_fn(v.len(), |i| v[i]);
+        let mut xor = SegTree::<Xor<u64>>::from(v.clone());
+        for _ in 0..scaled(2000) {
+            let i = rng.random_range(0..v.len());
+            let x = rng.random_range(0..1000) * 6;
+            v[i] = x;
+            sum.set(i, x);
+            gcd.set(i, x);
+            xor.set(i, x);
+
+            let l = rng.random_range(0..=v.len());
+            let r = rng.random_range(l..=v.len());
+            assert_eq!(sum.query(l..r), v[l..r].iter().sum());
+            assert_eq!(xor.query(l..r), v[l..r].iter().fold(0, |a, b| a ^ b));
+            let g = v[l..r].iter().fold(0, |a, &b| Gcd::<u64>::op(&a, &b));
+            assert_eq!(gcd.query(l..r), g);
+        }
+
+        // x -> 2x + 1, then x -> 3x, then x -> x - 4
+        let maps = [(2, 1), (3, 0), (1, -4)];
+        let seg: SegTree<Affine<i64>> = maps.into_iter().collect();
+        assert_eq!(seg.query(..), (6, -1));
+        assert_eq!(seg.query(1..), (3, -4));
+    }
+
+    #[test]
+    fn test_lazy_segtree() {
+        use crate::trees::lazy_segment_tree::{Add, Assign};
+
+        let mut rng = rand::rng();
+        let n = 200;
+        let mut range = |rng: &mut ThreadRng| {
+            let l = rng.random_range(0..=n);
+            (l, rng.random_range(l..=n))
+        };
+
+        let mut v: Vec<i64> = (0..n).map(|_| rng.random_range(-100..100)).collect();
+        let mut sum = LazySegTree::<Sum<i64>, Add<i64>>::from(v.clone());
+        let mut min = LazySegTree::<Min<i64>, Assign<i64>>::from(v.clone());
+        let mut w = v.clone();
+
+        for _ in 0..scaled(5000) {
+            let (l, r) = range(&mut rng);
+            let x = rng.random_range(-100..100);
+            match rng.random_range(0..4) {
+                0 => {
+                    sum.apply(l..r, x);
+                    v[l..r].iter_mut().for_each(|y| *y += x);
+                }
+                1 => {
+                    min.apply(l..r, Some(x));
+                    w[l..r].iter_mut().for_each(|y| *y = x);
+                }
+                2 if l < n => {
+                    sum.set(l, x);
+                    v[l] = x;
+                    assert_eq!(min.get(l), w[l]);
+                }
+                _ => {
+                    assert_eq!(sum.query(l..r), v[l..r].iter().sum());
+                    let m = w[l..r].iter().copied().min().unwrap_or(i64::MAX);
+                    assert_eq!(min.query(l..r), m);
+                }
+            }
+        }
+        assert_eq!(sum.query(..), v.iter().sum());
+        assert!((0..n).all(|i| min.get(i) == w[i]));
+
+        let mut u: Vec<i64> = (0..n as i64).collect();
+        let mut affine = LazySegTree::<Sum<i64>, Affine<i64>>::from(u.clone());
+        for _ in 0..scaled(2000) {
+            let (l, r) = range(&mut rng);
+            let (a, b) = (rng.random_range(-1..=1), rng.random_range(-5..5));
+            affine.apply(l..r, (a, b));
+            u[l..r].iter_mut().for_each(|y| *y = a * *y + b);
+            let (l, r) = range(&mut rng);
+            assert_eq!(affine.query(l..r), u[l..r].iter().sum());
+        }
+
+        // adds pass over the empty `None` entries `new` starts with
+        let mut min = LazySegTree::<Min<Option<i64>>, Add<i64>>::new(n);
+        let mut w = vec![None; n];
+        for _ in 0..scaled(3000) {
+            let (l, r) = range(&mut rng);
+            let x = rng.random_range(-100..100);
+            match rng.random_range(0..3) {
+                0 => {
+                    min.apply(l..r, x);
+                    w[l..r].iter_mut().flatten().for_each(|y| *y += x);
+                }
+                1 if l < n => {
+                    min.set(l, Some(x));
+                    w[l] = Some(x);
+                }
+                _ => {
+                    assert_eq!(min.query(l..r), w[l..r].iter().flatten().copied().min());
+                }
+            }
+        }
+
+        // a stored `i64::MAX` is an element like any other
+        let v = vec![Some(i64::MAX), Some(0)];
+        let mut min = LazySegTree::<Min<Option<i64>>, Add<i64>>::from(v);
+        min.apply(.., -1);
+        assert_eq!(min.get(0), Some(i64::MAX - 1));
+        assert_eq!(min.query(..), Some(-1));
+    }
+
+    #[test]
+    fn test_segtree_descent() {
+        use crate::trees::lazy_segment_tree::Add;
+
+        let mut rng = rand::rng();
+        for n in [0, 1, 5, 64, scaled(300)] {
+            let mut v: Vec<u64> = (0..n).map(|_| rng.random_range(0..100)).collect();
+            let mut seg = SegTree::<Sum<u64>>::from(v.clone());
+            let mut lazy = LazySegTree::<Sum<u64>, Add<u64>>::from(v.clone());
+
+            for _ in 0..scaled(500) {
+                if n > 0 {
+                    let l = rng.random_range(0..n);
+                    let r = rng.random_range(l..=n);
+                    let x = rng.random_range(0..10);
+                    lazy.apply(l..r, x);
+                    for (i, y) in v.iter_mut().enumerate().take(r).skip(l) {
+                        *y += x;
+                        seg.set(i, *y);
+                    }
+                }
+
+                let k = rng.random_range(0..2000);
+                let l = rng.random_range(0..=n);
+                // the first position where the running sum from `l` exceeds `k`
+                let mut acc = 0;
+                let right = l + v[l..].iter().take_while(|&&y| {
+                    acc += y;
+                    acc <= k
+                }).count();
+                assert_eq!(seg.max_right(l, |&s| s <= k), right);
+                assert_eq!(lazy.max_right(l, |&s| s <= k), right);
+
+                let mut acc = 0;
+                let left = l - v[..l].iter().rev().take_while(|&&y| {
+                    acc += y;
+                    acc <= k
+                }).count();
+                assert_eq!(seg.min_left(l, |&s| s <= k), left);
+                assert_eq!(lazy.min_left(l, |&s| s <= k), left);
+            }
+        }
+
+        let seg = SegTree::<Max<i32>>::from(vec![3, 1, 4, 1, 5, 9, 2, 6]);
+        assert_eq!(seg.max_right(0, |&m| m < 5), 4);
+        assert_eq!(seg.max_right(6, |&m| m < 5), 7);
+        assert_eq!(seg.min_left(8, |&m| m < 9), 6);
+        assert_eq!(seg.min_left(5, |&m| m < 9), 0);
+    }
+
+    #[test]
+    fn test_sparse_segtree() {
+        use crate::trees::lazy_segment_tree::{Add, Assign};
+
+        let mut rng = rand::rng();
+        let (lo, hi) = (-1_000_000_000_000_000_000, 1_000_000_000_000_000_000);
+        let mut seg = SparseSegTree::<Sum<i64>>::new(lo, hi);
+        let mut b = BTreeMap::new();
+        let q = scaled(2000);
+        for _ in 0..q {
+            let i = rng.random_range(lo..hi);
+            let x = rng.random_range(0..1000);
+            seg.set(i, x);
+            b.insert(i, x);
+
+            let l = rng.random_range(lo..hi);
+            let r = rng.random_range(l..=hi);
+            assert_eq!(seg.query(l..r), b.range(l..r).map(|x| x.1).sum());
+            assert_eq!(seg.get(i), x);
+
+            let k = rng.random_range(0..5000);
+            let mut acc = 0;
+            let right = b.range(l..).find(|x| {
+                acc += x.1;
+                acc > k
+            });
+            assert_eq!(seg.max_right(l, |&s| s <= k), right.map_or(hi, |x| *x.0));
+            let mut acc = 0;
+            let left = b.range(..r).rev().find(|x| {
+                acc += x.1;
+                acc > k
+            });
+            assert_eq!(seg.min_left(r, |&s| s <= k), left.map_or(lo, |x| x.0 + 1));
+        }
+        assert_eq!(seg.query(..), b.values().sum());
+        assert!(seg.node_count() <= q * 62);
+
+        // lazy updates on a small window far from zero, against a vec
+        let (base, n) = (1_000_000_000_000, 300);
+        let mut sum = SparseSegTree::<Sum<i64>, Add<i64>>::new(base, base + n as i64);
+        let mut min = SparseSegTree::<Min<i64>, Assign<i64>>::new(base, base + n as i64);
+        min.apply(.., Some(0));
+        let (mut v, mut w) = (vec![0i64; n], vec![0i64; n]);
+        for _ in 0..scaled(3000) {
+            let l = rng.random_range(0..=n);
+            let r = rng.random_range(l..=n);
+            let (gl, gr) = (base + l as i64, base + r as i64);
+            let x = rng.random_range(0..100);
+            match rng.random_range(0..4) {
+                0 => {
+                    sum.apply(gl..gr, x);
+                    v[l..r].iter_mut().for_each(|y| *y += x);
+                }
+                1 => {
+                    min.apply(gl..gr, Some(x));
+                    w[l..r].iter_mut().for_each(|y| *y = x);
+                }
+                2 if l < n => {
+                    sum.set(gl, x);
+                    min.set(gl, x);
+                    (v[l], w[l]) = (x, x);
+                }
+                _ => {
+                    assert_eq!(sum.query(gl..gr), v[l..r].iter().sum());
+                    let m = w[l..r].iter().copied().min().unwrap_or(i64::MAX);
+                    assert_eq!(min.query(gl..gr), m);
+
+                    let k = rng.random_range(0..3000);
+                    let mut acc = 0;
+                    let right = l + v[l..].iter().take_while(|&&y| {
+                        acc += y;
+                        acc <= k
+                    }).count();
+                    assert_eq!(sum.max_right(gl, |&s| s <= k), base + right as i64);
+                }
+            }
+        }
+        assert!((0..n).all(|i| sum.get(base + i as i64) == v[i]));
+
+        // adds over mostly unwritten ranges leave the empty gaps alone
+        let mut min = SparseSegTree::<Min<Option<i64>>, Add<i64>>::new(lo, hi);
+        let mut w = vec![None; n];
+        for _ in 0..scaled(3000) {
+            let l = rng.random_range(0..=n);
+            let r = rng.random_range(l..=n);
+            let (gl, gr) = (base + l as i64, base + r as i64);
+            let x = rng.random_range(-100..100);
+            match rng.random_range(0..3) {
+                0 => {
+                    min.apply(gl..gr, x);
+                    w[l..r].iter_mut().flatten().for_each(|y| *y += x);
+                }
+                1 if l < n => {
+                    min.set(gl, Some(x));
+                    w[l] = Some(x);
+                }
+                _ => {
+                    assert_eq!(min.query(gl..gr), w[l..r].iter().flatten().copied().min());
+                }
+            }
+        }
+        min.apply(.., 1);
+        assert_eq!(min.query(..), w.iter().flatten().min().map(|m| m + 1));
+
+        // a written `i64::MAX` moves, the unwritten indices around it don't
+        let mut min = SparseSegTree::<Min<Option<i64>>, Add<i64>>::new(0, 1_000_000_000);
+        min.set(5, Some(i64::MAX));
+        min.apply(0..100, -3);
+        assert_eq!(min.get(5), Some(i64::MAX - 3));
+        assert_eq!(min.get(6), None);
+        assert_eq!(min.query(..), Some(i64::MAX - 3));
+    }
+
+    #[test]
+    fn test_seg_forest() {
+        let mut rng = rand::rng();
+        let n = scaled(400);
+        let vmax = 1_000_000_000;
+        let par: Vec<usize> = (0..n).map(|i| rng.random_range(0..i.max(1))).collect();
+        let val: Vec<i64> = (0..n).map(|_| rng.random_range(0..vmax)).collect();
+
+        // values in each subtree, children have larger ids than parents
+        let mut sub: Vec<Vec<i64>> = val.iter().map(|&x| vec![x]).collect();
+        for i in (1..n).rev() {
+            let s = sub[i].clone();
+            sub[par[i]].extend(s);
+        }
+
+        // per-vertex count trees indexed by value, merged bottom-up
+        let mut f = SegForest::<Sum<i64>>::new(0, vmax);
+        let mut trees: Vec<_> = (0..n).map(|_| f.empty()).collect();
+        for i in 0..n {
+            f.set(&mut trees[i], val[i], 1);
+        }
+        let mut root = f.empty();
+        for i in (0..n).rev() {
+            let t = trees.pop().unwrap();
+            let mut s = sub[i].clone();
+            s.sort();
+            assert_eq!(f.query(&t, ..), s.len() as i64);
+            let (l, r) = (rng.random_range(0..vmax), rng.random_range(0..vmax));
+            let cnt = s.iter().filter(|&&x| l.min(r) <= x && x < l.max(r)).count();
+            assert_eq!(f.query(&t, l.min(r)..l.max(r)), cnt as i64);
+            let k = rng.random_range(0..s.len());
+            assert_eq!(f.max_right(&t, 0, |&c| c <= k as i64), s[k]);
+
+            if i > 0 {
+                let p = std::mem::replace(&mut trees[par[i]], f.empty());
+                trees[par[i]] = f.merge(p, t, |a, b| a + b);
+            } else {
+                root = t;
+            }
+        }
+
+        let mut all = sub[0].clone();
+        all.sort();
+        assert_eq!(f.get(&root, all[0]), all.iter().filter(|&&x| x == all[0]).count() as i64);
+        let nodes = f.node_count();
+        for _ in 0..scaled(50) {
+            let at = rng.random_range(0..=vmax);
+            let (a, b) = f.split(root, at);
+            let below = all.iter().filter(|&&x| x < at).count() as i64;
+            assert_eq!(f.query(&a, ..), below);
+            assert_eq!(f.query(&b, ..), n as i64 - below);
+            assert_eq!(f.query(&a, at..), 0);
+            root = f.merge(b, a, |_, _| unreachable!());
+            assert_eq!(f.node_count(), nodes);
+        }
+        f.clear(root);
+        assert_eq!(f.node_count(), 0);
+
+        // lazy adds carried through merges and splits, against vecs
+        use crate::trees::lazy_segment_tree::Add;
+        let (base, w) = (1_000_000_000_000, 64);
+        let mut f = SegForest::<Sum<i64>, Add<i64>>::new(0, 2 * base);
+        let mut trees = vec![(f.empty(), vec![0i64; w])];
+        for _ in 0..scaled(2000) {
+            let i = rng.random_range(0..trees.len());
+            let l = rng.random_range(0..=w);
+            let r = rng.random_range(l..=w);
+            let (gl, gr) = (base + l as i64, base + r as i64);
+            let x = rng.random_range(0..100);
+            match rng.random_range(0..4) {
+                0 if l < w => {
+                    f.set(&mut trees[i].0, gl, x);
+                    trees[i].1[l] = x;
+                }
+                1 => {
+                    f.apply(&mut trees[i].0, gl..gr, x);
+                    trees[i].1[l..r].iter_mut().for_each(|y| *y += x);
+                }
+                2 if trees.len() > 1 => {
+                    let (b, v) = trees.swap_remove(i);
+                    let a = &mut trees[0];
+                    let t = std::mem::replace(&mut a.0, f.empty());
+                    a.0 = f.merge(t, b, |x, y| x + y);
+                    a.1.iter_mut().zip(v).for_each(|(y, z)| *y += z);
+                }
+                _ if trees.len() < 4 => {
+                    let t = std::mem::replace(&mut trees[i].0, f.empty());
+                    let (a, b) = f.split(t, gl);
+                    let mut hi = trees[i].1.clone();
+                    hi[..l].fill(0);
+                    trees[i].1[l..].fill(0);
+                    trees[i].0 = a;
+                    trees.push((b, hi));
+                }
+                _ => {}
+            }
+            let (t, v) = &trees[rng.random_range(0..trees.len())];
+            assert_eq!(f.query(t, gl..gr), v[l..r].iter().sum());
+            let k = rng.random_range(0..500);
+            let mut acc = 0;
+            let left = r - v[..r].iter().rev().take_while(|&&y| {
+                acc += y;
+                acc <= k
+            }).count();
+            let expected = if left == 0 { 0 } else { base + left as i64 };
+            assert_eq!(f.min_left(t, gr, |&s| s <= k), expected);
+        }
+    }
+
+    #[test]
+    fn test_segtree_beats() {
+        let mut rng = rand::rng();
+        for n in [1, 2, 7, scaled(200)] {
+            let mut v: Vec<i64> = (0..n).map(|_| rng.random_range(-1000..1000)).collect();
+            let mut seg = SegTreeBeats::from(v.clone());
+            for _ in 0..scaled(5000) {
+                let l = rng.random_range(0..=n);
+                let r = rng.random_range(l..=n);
+                let x = rng.random_range(-1000..1000);
+                match rng.random_range(0..4) {
+                    0 => {
+                        seg.chmin(l..r, x);
+                        v[l..r].iter_mut().for_each(|y| *y = (*y).min(x));
+                    }
+                    1 => {
+                        seg.chmax(l..r, x);
+                        v[l..r].iter_mut().for_each(|y| *y = (*y).max(x));
+                    }
+                    2 => {
+                        seg.add(l..r, x / 10);
+                        v[l..r].iter_mut().for_each(|y| *y += x / 10);
+                    }
+                    _ => {
+                        let s = &v[l..r];
+                        assert_eq!(seg.sum(l..r), s.iter().sum());
+                        assert_eq!(seg.min(l..r), s.iter().copied().min().unwrap_or(i64::MAX));
+                        assert_eq!(seg.max(l..r), s.iter().copied().max().unwrap_or(i64::MIN));
+                    }
+                }
+            }
+            assert!((0..n).all(|i| seg.sum(i..=i) == v[i]));
+        }
 
-        seg.insert(50, 1);
-        assert_eq!(seg.query(45, 50), Some(49));
-        assert_eq!(seg.query(0, 200), None);
+        let mut seg = SegTreeBeats::new(0);
+        assert_eq!(seg.sum(..), 0);
+        seg.chmin(.., 5);
     }
 
     #[test]
@@ -171,8 +645,9 @@ mod tests {
         for (k, v) in v.iter().rev() {
             t.insert(*k, *v);
         }
-        assert!(t.remove(&0));
-        assert!(t.remove(&5));
+        assert_eq!(t.remove(&0), Some((0, 0)));
+        assert_eq!(t.remove(&5), Some((5, 5)));
+        assert_eq!(t.remove(&5), None);
 
         v.remove(5);
         v.remove(0);
@@ -184,7 +659,7 @@ mod tests {
         assert_eq!(v, t.into_iter().collect::<Vec<_>>());
 
         let mut rng = rand::thread_rng();
-        let n = 10000;
+        let n = scaled(10000);
         let mut tr = TreapSet::new();
         let mut b = BTreeSet::new();
 
@@ -199,6 +674,579 @@ mod tests {
             assert_eq!(b.remove(r), tr.remove(r));
             assert_eq!(b.remove(r), tr.remove(r));
         }
+
+        let mut t = TreapMap::new();
+        let mut b = BTreeMap::new();
+        for i in 0..2000u64 {
+            let k = (rand::random::<u8>().to_string(), i % 7);
+            t.insert(k.clone(), i);
+            b.insert(k, i);
+        }
+        for _ in 0..2000 {
+            let k = (rand::random::<u8>().to_string(), rand::random::<u64>() % 7);
+            assert_eq!(t.remove(&k), b.remove_entry(&k));
+        }
+        assert!(t.iter().eq(b.iter()));
+    }
+
+    #[test]
+    fn test_treap_order_stats() {
+        let mut rng = rand::rng();
+        let mut t = TreapSet::new();
+        let mut b = BTreeSet::new();
+        for _ in 0..scaled(5000) {
+            let r = rng.random_range(0..2000);
+            if rng.random_bool(0.3) {
+                assert_eq!(t.remove(&r), b.remove(&r));
+            } else {
+                t.insert(r);
+                b.insert(r);
+            }
+        }
+        assert_eq!(t.len(), b.len());
+
+        let v: Vec<_> = b.iter().copied().collect();
+        for (i, x) in v.iter().enumerate() {
+            assert_eq!(t.nth(i), Some(x));
+            assert_eq!(t.rank(x), i);
+        }
+        assert_eq!(t.nth(v.len()), None);
+        assert_eq!(t.rank(&2000), v.len());
+
+        let k = v.len() / 3;
+        let (l, r) = t.split_at_rank(k);
+        assert_eq!(l.len(), k);
+        assert!(l.iter().map(|x| x.0).eq(v[..k].iter()));
+        assert!(r.iter().map(|x| x.0).eq(v[k..].iter()));
+
+        let t = TreapMap::from((0..100).map(|i| (i, i * i)).collect());
+        t.validate().unwrap();
+        assert_eq!(t.nth(7), Some((&7, &49)));
+        assert_eq!(t.rank(&50), 50);
+    }
+
+    #[test]
+    fn test_treap_set_ops() {
+        let mut rng = rand::rng();
+        for _ in 0..20 {
+            let (n, m) = (rng.random_range(0..300), rng.random_range(0..300));
+            let a: BTreeSet<i32> = (0..n).map(|_| rng.random_range(0..400)).collect();
+            let b: BTreeSet<i32> = (0..m).map(|_| rng.random_range(0..400)).collect();
+            let set = |s: &BTreeSet<i32>| TreapSet::from(s.iter().copied().collect());
+            let check = |t: TreapSet<i32>, v: Vec<&i32>| {
+                assert_eq!(t.len(), v.len());
+                assert!(t.iter().map(|x| x.0).eq(v));
+            };
+
+            check(set(&a).union(set(&b)), a.union(&b).collect());
+            check(set(&a).intersection(set(&b)), a.intersection(&b).collect());
+            check(set(&a).difference(set(&b)), a.difference(&b).collect());
+            check(set(&a).symmetric_difference(set(&b)), a.symmetric_difference(&b).collect());
+        }
+
+        let a = TreapMap::from((0..100).map(|i| (i, i)).collect());
+        let b = TreapMap::from((50..150).map(|i| (i, 1000)).collect());
+        let u = a.union_with(b, |_, x, y| x + y);
+        u.validate().unwrap();
+        assert_eq!(u.len(), 150);
+        assert_eq!(u.get(&10), Some(&10));
+        assert_eq!(u.get(&60), Some(&1060));
+        assert_eq!(u.get(&120), Some(&1000));
+
+        let a = TreapMap::from((0..100).map(|i| (i, i.to_string())).collect());
+        let b = TreapMap::from((50..150).map(|i| (i, "x".to_string())).collect());
+        let t = a.intersection_with(b, |_, x, y| y + &x);
+        t.validate().unwrap();
+        assert_eq!(t.len(), 50);
+        assert_eq!(t.get(&70).map(|s| s.as_str()), Some("x70"));
+    }
+
+    #[test]
+    fn test_pst_treap() {
+        let mut rng = rand::rng();
+        let mut versions = vec![(PstTreapMap::new(), BTreeMap::new())];
+        for _ in 0..scaled(3000) {
+            let (t, b) = versions.last().unwrap();
+            let (mut t, mut b) = (t.clone(), b.clone());
+            let k = rng.random_range(0..500);
+            if rng.random_bool(0.3) {
+                t = t.remove(&k);
+                b.remove(&k);
+            } else {
+                t = t.insert(k, k * 2);
+                b.insert(k, k * 2);
+            }
+            versions.push((t, b));
+        }
+        for (t, b) in versions.iter().step_by(97) {
+            t.validate().unwrap();
+            assert_eq!(t.len(), b.len());
+            assert!(t.iter().eq(b.iter()));
+        }
+
+        // the shape only depends on the key set
+        let a = (0..200).fold(PstTreapMap::new(), |t, i| t.insert(i, ()));
+        let b = (0..300).rev().fold(PstTreapMap::new(), |t, i| t.insert(i, ()));
+        let b = (200..300).fold(b, |t, i| t.remove(&i));
+        assert_eq!(format!("{:?}", a), format!("{:?}", b));
+    }
+
+    #[test]
+    fn test_link_cut() {
+        /// Path contents in order, to check non-commutative aggregates.
+        struct Concat;
+        impl Monoid for Concat {
+            type T = Vec<usize>;
+            fn identity() -> Vec<usize> {
+                vec![]
+            }
+            fn op(a: &Vec<usize>, b: &Vec<usize>) -> Vec<usize> {
+                [a.as_slice(), b].concat()
+            }
+        }
+
+        // path from `u` to `v` in a naive adjacency-list forest
+        fn path(adj: &[Vec<usize>], u: usize, v: usize) -> Option<Vec<usize>> {
+            let mut par = vec![usize::MAX; adj.len()];
+            let mut stack = vec![u];
+            par[u] = u;
+            while let Some(x) = stack.pop() {
+                for &y in &adj[x] {
+                    if par[y] == usize::MAX {
+                        par[y] = x;
+                        stack.push(y);
+                    }
+                }
+            }
+            if par[v] == usize::MAX {
+                return None;
+            }
+            let mut p = vec![v];
+            while *p.last().unwrap() != u {
+                p.push(par[*p.last().unwrap()]);
+            }
+            p.reverse();
+            Some(p)
+        }
+
+        let mut rng = rand::rng();
+        let n = 40;
+        let mut lct = LinkCutTree::<Concat>::from_values((0..n).map(|i| vec![i]).collect());
+        let mut adj = vec![vec![]; n];
+        let mut edges = vec![];
+
+        for _ in 0..scaled(20000) {
+            let (u, v) = (rng.random_range(0..n), rng.random_range(0..n));
+            match rng.random_range(0..6) {
+                0 | 1 => {
+                    let ok = path(&adj, u, v).is_none();
+                    assert_eq!(lct.link(u, v), ok);
+                    if ok {
+                        adj[u].push(v);
+                        adj[v].push(u);
+                        edges.push((u, v));
+                    }
+                }
+                2 if !edges.is_empty() => {
+                    let (a, b) = edges.swap_remove(rng.random_range(0..edges.len()));
+                    let (a, b) = if rng.random_bool(0.5) { (a, b) } else { (b, a) };
+                    assert!(lct.cut(a, b));
+                    adj[a].retain(|&x| x != b);
+                    adj[b].retain(|&x| x != a);
+                    assert!(!lct.connected(a, b));
+                }
+                2 => assert_eq!(lct.cut(u, v), false),
+                3 => {
+                    let p = path(&adj, u, v);
+                    assert_eq!(lct.connected(u, v), p.is_some());
+                    assert_eq!(lct.path_query(u, v), p);
+                }
+                _ => {
+                    let r = rng.random_range(0..n);
+                    lct.evert(r);
+                    let (pu, pv) = (path(&adj, r, u), path(&adj, r, v));
+                    match pu.zip(pv) {
+                        Some((pu, pv)) => {
+                            let common = pu.iter().zip(&pv).take_while(|(a, b)| a == b).count();
+                            assert_eq!(lct.lca(u, v), Some(pu[common - 1]));
+                            assert_eq!(lct.find_root(u), r);
+                        }
+                        // rooted elsewhere, only connectivity is known
+                        None => assert_eq!(lct.lca(u, v).is_some(), path(&adj, u, v).is_some()),
+                    }
+                }
+            }
+        }
+
+        lct.set(0, vec![100]);
+        assert_eq!(lct.get(0), &vec![100]);
+        assert_eq!(lct.path_query(0, 0), Some(vec![100]));
+    }
+
+    #[test]
+    fn test_skip_list_rank() {
+        let mut rng = rand::rng();
+        let mut skip = SkipListMap::new();
+        let mut b = BTreeMap::new();
+        for i in 0..scaled(5000) {
+            let k = rng.random_range(0..3000);
+            match rng.random_range(0..4) {
+                0 => assert_eq!(skip.remove(&k), b.remove(&k).is_some()),
+                1 if !b.is_empty() => {
+                    let r = rng.random_range(0..b.len());
+                    let key = *b.keys().nth(r).unwrap();
+                    assert_eq!(skip.remove_at(r), b.remove_entry(&key));
+                }
+                _ => {
+                    skip.insert(k, i);
+                    b.insert(k, i);
+                }
+            }
+        }
+        assert_eq!(skip.validate(), Ok(()));
+        assert_eq!(skip.remove_at(b.len()), None);
+
+        let v: Vec<_> = b.iter().collect();
+        for (i, &(k, val)) in v.iter().enumerate() {
+            assert_eq!(skip.nth(i), Some((k, val)));
+            assert_eq!(skip.rank(k), i);
+        }
+        assert_eq!(skip.nth(v.len()), None);
+        assert_eq!(skip.rank(&3000), v.len());
+
+        let m = v.len() / 2;
+        assert!(skip.range_by_rank(m..m + 10).eq(v[m..m + 10].iter().copied()));
+        assert!(skip.range_by_rank(m..).eq(v[m..].iter().copied()));
+        assert!(skip.range_by_rank(..=m).eq(v[..=m].iter().copied()));
+
+        let mut set = SkipListSet::new();
+        [5, 1, 4, 2, 3].into_iter().for_each(|x| {
+            set.insert(x);
+        });
+        assert_eq!(set.nth(1), Some(&2));
+        assert_eq!(set.rank(&4), 3);
+        assert_eq!(set.remove_at(0), Some(1));
+        assert_eq!(set.range_by_rank(1..3).collect::<Vec<_>>(), [&3, &4]);
+    }
+
+    #[test]
+    fn test_skip_list_iter() {
+        let mut rng = rand::rng();
+        let mut skip = SkipListMap::new();
+        let mut b = BTreeMap::new();
+        assert_eq!(skip.first_key_value(), None);
+        for i in 0..2000 {
+            let k = rng.random_range(0..1000);
+            skip.insert(k, i);
+            b.insert(k, i);
+            if i % 3 == 0 {
+                let k = rng.random_range(0..1000);
+                skip.remove(&k);
+                b.remove(&k);
+            }
+        }
+        assert_eq!(skip.validate(), Ok(()));
+        assert!(skip.iter().eq(b.iter()));
+        assert!(skip.iter().rev().eq(b.iter().rev()));
+        assert_eq!(skip.first_key_value(), b.first_key_value());
+        assert_eq!(skip.last_key_value(), b.last_key_value());
+
+        for _ in 0..200 {
+            let (lo, hi) = (rng.random_range(-10..1010), rng.random_range(-10..1010));
+            if lo <= hi {
+                assert!(skip.range(lo..hi).eq(b.range(lo..hi)));
+                assert!(skip.range(lo..=hi).rev().eq(b.range(lo..=hi).rev()));
+            }
+            assert!(skip.range(..hi).eq(b.range(..hi)));
+            assert!(skip.range(lo..).rev().eq(b.range(lo..).rev()));
+        }
+
+        let mut it = skip.range(100..900);
+        let mut bit = b.range(100..900);
+        loop {
+            let (x, y) = if rng.random_bool(0.5) {
+                (it.next(), bit.next())
+            } else {
+                (it.next_back(), bit.next_back())
+            };
+            assert_eq!(x, y);
+            if x.is_none() {
+                break;
+            }
+        }
+
+        for (_, v) in skip.iter_mut() {
+            *v += 1;
+        }
+        for (_, v) in b.iter_mut() {
+            *v += 1;
+        }
+        assert!((&skip).into_iter().eq(b.iter()));
+
+        let mut it = skip.into_iter();
+        assert_eq!(it.next_back(), b.pop_last());
+        assert_eq!(it.next(), b.pop_first());
+        assert!(it.take(10).eq(b.into_iter().take(10)));
+    }
+
+    #[test]
+    fn test_seeded_shapes() {
+        use crate::lists::skip_list::SkipListConfig;
+        use crate::misc::rng::{clear_seed, set_seed, with_local_rng};
+        use crate::trees::binary_tree::{BinaryTree, BinaryTreeNode};
+        use rand::rngs::StdRng;
+
+        fn preorder<T: BinaryTree>(t: &T) -> Vec<<T::Node as BinaryTreeNode>::Key>
+        where
+            <T::Node as BinaryTreeNode>::Key: Clone,
+        {
+            let mut keys = vec![];
+            let mut stk: Vec<_> = t.root().into_iter().collect();
+            while let Some(p) = stk.pop() {
+                let n = unsafe { p.as_ref() };
+                keys.push(n.kv().0.clone());
+                stk.extend(n.right().iter().chain(n.left()));
+            }
+            keys
+        }
+
+        let keys: Vec<i32> = (0..1000).map(|i| i * 7919 % 1000).collect();
+        let treap = |mut t: TreapMap<i32, ()>| {
+            for &k in &keys {
+                t.insert(k, ());
+            }
+            for k in keys.iter().step_by(3) {
+                t.remove(k);
+            }
+            t.validate().unwrap();
+            preorder(&t)
+        };
+        let skip = |mut s: SkipListMap<i32, ()>| {
+            for &k in &keys {
+                s.insert(k, ());
+            }
+            for k in keys.iter().step_by(3) {
+                s.remove(k);
+            }
+            s.validate().unwrap();
+            (s.height(), format!("{:?}", s))
+        };
+
+        let a = treap(TreapMap::with_rng(StdRng::seed_from_u64(7)));
+        assert_eq!(a, treap(TreapMap::with_rng(StdRng::seed_from_u64(7))));
+        assert_ne!(a, treap(TreapMap::with_rng(StdRng::seed_from_u64(8))));
+        // any `RngCore` will do, a borrowed one included
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut t = TreapMap::with_rng(&mut rng);
+        for &k in &keys {
+            t.insert(k, ());
+        }
+        for k in keys.iter().step_by(3) {
+            t.remove(k);
+        }
+        t.validate().unwrap();
+        assert_eq!(a, preorder(&t));
+
+        let config = || SkipListConfig {
+            p: 0.5,
+            max_level: 6,
+            rng: StdRng::seed_from_u64(7),
+        };
+        let a = skip(SkipListMap::with_config(config()));
+        assert!(a.0 <= 6);
+        assert_eq!(a, skip(SkipListMap::with_config(config())));
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut s = SkipListMap::with_config(SkipListConfig { p: 0.5, max_level: 6, rng: &mut rng });
+        for &k in &keys {
+            s.insert(k, ());
+        }
+        for k in keys.iter().step_by(3) {
+            s.remove(k);
+        }
+        assert_eq!(a, (s.height(), format!("{:?}", s)));
+
+        // the crate-wide seed covers structures built with `new`
+        set_seed(42);
+        let (t, s) = (treap(TreapMap::new()), skip(SkipListMap::new()));
+        set_seed(42);
+        assert_eq!(t, treap(TreapMap::new()));
+        assert_eq!(s, skip(SkipListMap::new()));
+        // and the per-thread rng of the concurrent skip list
+        set_seed(42);
+        let draw = || with_local_rng(|r| r.random::<u64>());
+        let x = draw();
+        set_seed(42);
+        assert_eq!(x, draw());
+        clear_seed();
+    }
+
+    #[test]
+    fn test_concurrent_skip_list() {
+        use crate::lists::concurrent_skip_list::pin;
+
+        let m = ConcurrentSkipListMap::new();
+        let mut b = BTreeMap::new();
+        for i in 0..2000 {
+            let k = rand::random::<u16>() % 1000;
+            // no overwrite, unlike BTreeMap::insert
+            assert_eq!(m.insert(k, i), !b.contains_key(&k));
+            b.entry(k).or_insert(i);
+            if i % 3 == 0 {
+                let k = rand::random::<u16>() % 1000;
+                assert_eq!(m.remove(&k), b.remove(&k).is_some());
+            }
+        }
+        let guard = &pin();
+        assert_eq!(m.len(), b.len());
+        assert!(m.iter(guard).eq(b.iter()));
+        for k in 0..1000 {
+            assert_eq!(m.get(&k, guard), b.get(&k));
+        }
+
+        // disjoint writers, every thread checks its own keys
+        let n = scaled(20000) as u32;
+        let m = ConcurrentSkipListMap::new();
+        std::thread::scope(|s| {
+            for t in 0..4u32 {
+                let m = &m;
+                s.spawn(move || {
+                    for i in (t..n).step_by(4) {
+                        assert!(m.insert(i, i));
+                    }
+                    for i in (t..n).step_by(8) {
+                        assert!(m.remove(&i));
+                    }
+                    for i in (t..n).step_by(4) {
+                        assert_eq!(m.contains_key(&i), i % 8 >= 4);
+                    }
+                });
+            }
+        });
+        assert_eq!(m.len(), n as usize / 2);
+        assert!(m.iter(&pin()).map(|x| *x.0).eq((0..n).filter(|i| i % 8 >= 4)));
+
+        // contended writers on a few keys, with readers iterating
+        let m = ConcurrentSkipListMap::new();
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..n {
+                        let k = rand::random::<u8>() % 32;
+                        if rand::random::<bool>() {
+                            m.insert(k, k);
+                        } else {
+                            m.remove(&k);
+                        }
+                    }
+                });
+            }
+            s.spawn(|| {
+                for _ in 0..100 {
+                    let guard = &pin();
+                    let keys: Vec<_> = m.iter(guard).map(|x| *x.0).collect();
+                    assert!(keys.windows(2).all(|w| w[0] < w[1]));
+                    for k in 0..32 {
+                        assert!(m.get(&k, guard).is_none_or(|&v| v == k));
+                    }
+                }
+            });
+        });
+        let keys: Vec<_> = m.iter(&pin()).map(|(k, v)| { assert_eq!(k, v); *k }).collect();
+        assert_eq!(keys.len(), m.len());
+        assert!(keys.windows(2).all(|w| w[0] < w[1]));
+        for k in 0..32 {
+            assert_eq!(m.contains_key(&k), keys.contains(&k));
+        }
+    }
+
+    #[test]
+    fn test_memtable() {
+        use crate::lists::concurrent_skip_list::pin;
+        use crate::lists::memtable::Value;
+
+        let t = MemTable::new();
+        assert!(t.put("a", 1, 10));
+        assert!(t.put("b", 2, 20));
+        assert!(t.put("a", 3, 11));
+        assert!(t.delete("b", 4));
+        assert!(!t.put("a", 3, 12));
+        assert_eq!(t.last_seq(), 4);
+
+        let guard = &pin();
+        assert_eq!(t.get_at(&"a", 0, guard), None);
+        assert_eq!(t.get_at(&"a", 2, guard), Some(&Value::Put(10)));
+        assert_eq!(t.get_at(&"a", 9, guard), Some(&Value::Put(11)));
+        assert_eq!(t.get_at(&"b", 3, guard), Some(&Value::Put(20)));
+        assert_eq!(t.get_at(&"b", 4, guard), Some(&Value::Delete));
+        assert_eq!(t.get_at(&"c", 4, guard), None);
+        assert!(t.versions(guard).map(|x| (*x.0, x.1)).eq([("a", 3), ("a", 1), ("b", 4), ("b", 2)]));
+
+        let s = t.snapshot_at(2);
+        assert!(s.iter().eq([(&"a", &10), (&"b", &20)]));
+        assert!(t.snapshot().iter().eq([(&"a", &11)]));
+
+        // a snapshot doesn't see writers with newer sequence numbers
+        let t = MemTable::new();
+        for k in 0..100u32 {
+            t.put(k, k as u64 + 1, 0);
+        }
+        let s = t.snapshot();
+        let seq = s.seq();
+        let n = scaled(5000) as u64;
+        std::thread::scope(|sc| {
+            sc.spawn(|| {
+                for i in 1..=n {
+                    let k = (i % 100) as u32;
+                    if i % 7 == 0 {
+                        t.delete(k, seq + i);
+                    } else {
+                        t.put(k, seq + i, i);
+                    }
+                }
+            });
+            for _ in 0..20 {
+                assert!(s.iter().map(|x| *x.0).eq(0..100));
+                assert!(s.iter().all(|x| *x.1 == 0));
+            }
+        });
+        assert_eq!(s.get(&7), Some(&0));
+        let latest = t.snapshot();
+        let i = (1..=n).rev().find(|i| i % 100 == 7).unwrap();
+        assert_eq!(latest.get(&7), (i % 7 != 0).then_some(&i));
+        // the last write of each key decides
+        assert_eq!(latest.iter().count(), (n - 99..=n).filter(|i| i % 7 != 0).count());
+
+        // writers finishing out of order don't change a taken snapshot
+        let t = MemTable::new();
+        t.put(1, 2, 2);
+        t.put(1, 3, 3);
+        assert_eq!(t.last_seq(), 0);
+        assert!(t.snapshot().iter().next().is_none());
+        t.put(2, 1, 1);
+        assert_eq!(t.last_seq(), 3);
+
+        let t = MemTable::new();
+        let next = std::sync::atomic::AtomicU64::new(1);
+        let mut views = vec![];
+        std::thread::scope(|sc| {
+            for _ in 0..4 {
+                sc.spawn(|| {
+                    for _ in 0..scaled(2000) {
+                        let seq = next.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                        t.put((seq % 50) as u32, seq, seq);
+                    }
+                });
+            }
+            for _ in 0..50 {
+                let s = t.snapshot();
+                let view: Vec<_> = s.iter().map(|(k, v)| (*k, *v)).collect();
+                views.push((s, view));
+            }
+        });
+        for (s, view) in &views {
+            assert!(s.iter().map(|(k, v)| (*k, *v)).eq(view.iter().copied()));
+        }
+        assert_eq!(t.last_seq(), next.into_inner() - 1);
     }
 
     #[test]
@@ -206,7 +1254,7 @@ mod tests {
         test_map!(SkipListMap);
         let mut rng = rand::thread_rng();
 
-        let n = 100000;
+        let n = scaled(100000);
 
         let mut skip = SkipListSet::new();
         timeit!("SkipList insert", {
@@ -249,7 +1297,7 @@ mod tests {
     fn test_rbtree() {
         test_map!(RBTreeMap);
 
-        let n = 400000;
+        let n = scaled(400000);
         timeit!("RBTree", {
             let mut t = RBTreeMap::new();
             for _ in 0..n {
@@ -271,13 +1319,168 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_rbtree_order_statistic() {
+        let mut t = RBTreeMap::new();
+        let mut b = BTreeMap::new();
+        for i in 0..2000 {
+            let k = rand::random::<i32>() % 5000;
+            t.insert(k, i);
+            b.insert(k, i);
+        }
+        for _ in 0..1000 {
+            let k = rand::random::<i32>() % 5000;
+            assert_eq!(t.remove(&k), b.remove(&k).is_some());
+        }
+
+        let v: Vec<_> = b.iter().collect();
+        for (i, &(k, val)) in v.iter().enumerate() {
+            assert_eq!(t.select(i), Some((k, val)));
+            assert_eq!(t.rank(k), i);
+        }
+        assert_eq!(t.select(v.len()), None);
+
+        for _ in 0..1000 {
+            let lo = rand::random::<i32>() % 5000;
+            let hi = rand::random::<i32>() % 5000;
+            assert_eq!(t.rank(&lo), b.range(..lo).count());
+            if lo <= hi {
+                assert_eq!(t.count_range(lo..hi), b.range(lo..hi).count());
+                assert_eq!(t.count_range(lo..=hi), b.range(lo..=hi).count());
+            } else {
+                assert_eq!(t.count_range(lo..hi), 0);
+            }
+            assert_eq!(t.count_range(..=hi), b.range(..=hi).count());
+        }
+        assert_eq!(t.count_range(..), b.len());
+    }
+
+    #[test]
+    fn test_rbtree_range() {
+        let mut t = RBTreeMap::new();
+        let mut b = BTreeMap::new();
+        for i in 0..2000 {
+            let k = rand::random::<i32>() % 5000;
+            t.insert(k, i);
+            b.insert(k, i);
+        }
+
+        assert!(t.iter().eq(b.iter()));
+        assert!(t.iter().rev().eq(b.iter().rev()));
+        assert!((&t).into_iter().eq(&b));
+
+        for _ in 0..500 {
+            let lo = rand::random::<i32>() % 5000;
+            let hi = lo + rand::random::<i32>().rem_euclid(1000);
+            assert!(t.range(lo..hi).eq(b.range(lo..hi)));
+            assert!(t.range(lo..=hi).rev().eq(b.range(lo..=hi).rev()));
+            assert!(t.range(..hi).eq(b.range(..hi)));
+
+            // alternate both ends until they meet
+            let mut r1 = t.range(lo..);
+            let mut r2 = b.range(lo..);
+            loop {
+                let x = r1.next();
+                assert_eq!(x, r2.next());
+                let y = r1.next_back();
+                assert_eq!(y, r2.next_back());
+                if x.is_none() || y.is_none() {
+                    break;
+                }
+            }
+        }
+
+        assert_eq!(t.into_iter().collect::<Vec<_>>(), b.into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_rbtree_join() {
+        let random_map = |n: usize, m: i32| {
+            let mut t = RBTreeMap::new();
+            let mut b = BTreeMap::new();
+            for _ in 0..n {
+                let (k, v) = (rand::random::<i32>().rem_euclid(m), rand::random::<u8>());
+                t.insert(k, v);
+                b.insert(k, v);
+            }
+            (t, b)
+        };
+        let check = |mut t: RBTreeMap<i32, u8>, mut b: BTreeMap<i32, u8>| {
+            assert_eq!(t.len(), b.len());
+            assert!(t.iter().eq(b.iter()));
+            for _ in 0..200 {
+                let k = rand::random::<i32>().rem_euclid(10000);
+                assert_eq!(t.remove(&k), b.remove(&k).is_some());
+                t.insert(k + 1, 0);
+                b.insert(k + 1, 0);
+            }
+            assert!(t.iter().eq(b.iter()));
+        };
+
+        for &(n, m) in &[(0, 1), (1, 10), (10, 100), (1000, 10000), (3000, 5000)] {
+            let (mut t, mut b) = random_map(n, 10000);
+            let key = rand::random::<i32>().rem_euclid(10000);
+            let t2 = t.split_off(&key);
+            let b2 = b.split_off(&key);
+            check(t2, b2);
+            check(t, b);
+
+            let (t1, b1) = random_map(n, 10000);
+            let (t2, b2) = random_map(m as usize, m);
+            let (mut u, mut ub) = (t1.union(t2), b1.clone());
+            ub.extend(b2.clone());
+            check(u, ub);
+
+            let (t1, b1) = random_map(n, 10000);
+            let (t2, b2) = random_map(m as usize, m);
+            let ib: BTreeMap<_, _> =
+                b1.iter().filter(|(k, _)| b2.contains_key(k)).map(|(k, v)| (*k, *v)).collect();
+            check(t1.intersection(t2), ib);
+
+            let (t1, b1) = random_map(n, 10000);
+            let (t2, b2) = random_map(m as usize, m);
+            let db: BTreeMap<_, _> =
+                b2.iter().filter(|(k, _)| !b1.contains_key(k)).map(|(k, v)| (*k, *v)).collect();
+            check(t2.difference(t1), db);
+
+            // disjoint append goes through join
+            let (mut t1, mut b1) = random_map(n, 10000);
+            let (mut t2, mut b2) = random_map(m as usize, 10000);
+            let mut t3 = t2.split_off(&5000);
+            let mut b3 = b2.split_off(&5000);
+            t3.append(&mut t2);
+            b3.append(&mut b2);
+            assert_eq!(t2.len(), 0);
+            t1.append(&mut t3);
+            b1.append(&mut b3);
+            check(t1, b1);
+
+            u = RBTreeMap::new();
+            ub = BTreeMap::new();
+            for i in 0..n as i32 {
+                u = RBTreeMap::join(u, i, 0, RBTreeMap::new());
+                ub.insert(i, 0);
+            }
+            let (mut t2, mut b2) = random_map(m as usize, m);
+            let mut t2 = t2.split_off(&-1);
+            let mut b2 = b2.split_off(&-1);
+            t2 = RBTreeMap::join(RBTreeMap::new(), -1, 1, t2);
+            b2.insert(-1, 1);
+            u = RBTreeMap::join(t2, m, 2, u.split_off(&(m + 1)));
+            let mut ub2 = ub.split_off(&(m + 1));
+            ub2.extend(b2);
+            ub2.insert(m, 2);
+            check(u, ub2);
+        }
+    }
+
     #[test]
     fn test_splay() {
         test_map!(SplayTreeMap);
 
-        let n = 400000;
+        let n = scaled(400000);
         timeit!("SplayTree", {
-            let mut t = RBTreeMap::new();
+            let mut t = SplayTreeMap::new();
             for _ in 0..n {
                 t.insert(rand::random::<i32>(), ());
             }
@@ -295,6 +1498,242 @@ mod tests {
                 t.remove(&rand::random::<i32>());
             }
         });
+
+        // sorted input degenerates into a path, which must not recurse
+        let n = scaled(1000000);
+        let mut t = SplayTreeMap::new();
+        for i in 0..n {
+            t.insert(i, i);
+        }
+        assert_eq!(t.get(&0), Some(&0));
+        for i in (0..n).step_by(2) {
+            assert!(t.remove(&i));
+        }
+        assert_eq!(t.len(), n / 2);
+        assert_eq!(t.get(&(n - 1)), Some(&(n - 1)));
+        assert_eq!(t.validate(), Ok(()));
+
+        // lookups through a shared reference
+        let t: SplayTreeMap<_, _> = {
+            let mut t = SplayTreeMap::new();
+            (0..100).for_each(|i| t.insert(i, i * i));
+            t
+        };
+        let lookup = |k| t.get(&k).copied();
+        let a = t.get(&3);
+        assert_eq!(lookup(9), Some(81));
+        assert_eq!(lookup(100), None);
+        assert_eq!(a, Some(&9));
+        assert!(t.contains_key(&99) && !t.contains_key(&-1));
+        assert_eq!(t.peek(&50), Some(&2500));
+        assert_eq!(t.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_splay_iter() {
+        let mut rng = rand::rng();
+        let mut t = SplayTreeMap::new();
+        let mut b = BTreeMap::new();
+        for i in 0..2000 {
+            let k = rng.random_range(0..1000);
+            t.insert(k, i);
+            b.insert(k, i);
+        }
+        assert!(t.iter().eq(b.iter()));
+        assert!(t.iter().rev().eq(b.iter().rev()));
+        assert_eq!(t.iter().len(), b.len());
+
+        let v: Vec<_> = b.keys().copied().collect();
+        for _ in 0..200 {
+            let (lo, hi) = (rng.random_range(-10..1010), rng.random_range(-10..1010));
+            if lo <= hi {
+                assert!(t.range(lo..hi).eq(b.range(lo..hi)));
+                assert!(t.range(lo..=hi).rev().eq(b.range(lo..=hi).rev()));
+            }
+            assert!(t.range(..hi).eq(b.range(..hi)));
+            assert!(t.range(lo..).eq(b.range(lo..)));
+            assert_eq!(t.rank(&lo), b.range(..lo).count());
+            let k = rng.random_range(0..v.len());
+            assert_eq!(t.nth(k), Some((&v[k], &b[&v[k]])));
+        }
+        assert_eq!(t.nth(v.len()), None);
+
+        // lookups while iterating
+        let mut it = t.range(100..200);
+        let mut n = 0;
+        while let Some((k, _)) = it.next() {
+            assert_eq!(t.get(&(k + 500)), b.get(&(k + 500)));
+            t.nth(0);
+            n += 1;
+        }
+        assert_eq!(n, b.range(100..200).count());
+        assert_eq!(t.validate(), Ok(()));
+
+        let mut it = t.iter();
+        let (front, back) = (it.next(), it.next_back());
+        assert_eq!(it.len(), b.len() - 2);
+        assert_eq!((front, back), (b.iter().next(), b.iter().next_back()));
+        assert!(t.into_iter().eq(b.into_iter()));
+    }
+
+    #[test]
+    fn test_entry() {
+        test_entry!(RBTreeMap);
+        test_entry!(TreapMap);
+        test_entry!(SplayTreeMap);
+        test_entry!(SkipListMap);
+
+        let mut t = TreapMap::new();
+        assert!(t.insert(1, 1));
+        assert!(!t.insert(1, 2));
+        assert_eq!(t.get(&1), Some(&2));
+    }
+
+    #[test]
+    fn test_treap_seq() {
+        use std::panic::{AssertUnwindSafe, catch_unwind};
+
+        // an owned sequence, movable and shareable across threads
+        fn shared<T: Send + Sync>() {}
+        shared::<TreapSeq<String>>();
+
+        // string concatenation, to catch aggregates taken in the wrong order
+        struct Concat;
+        impl Monoid for Concat {
+            type T = String;
+            fn identity() -> String {
+                String::new()
+            }
+            fn op(a: &String, b: &String) -> String {
+                a.clone() + b
+            }
+        }
+
+        let mut rng = rand::rng();
+        let mut s: TreapSeq<String, Concat> = TreapSeq::new();
+        let mut v: Vec<String> = vec![];
+
+        for _ in 0..3000 {
+            let n = v.len();
+            match rng.random_range(0..5) {
+                0 | 1 => {
+                    let i = rng.random_range(0..=n);
+                    let x = rng.random_range(0..100).to_string() + ",";
+                    s.insert_at(i, x.clone());
+                    v.insert(i, x);
+                }
+                2 if n > 0 => {
+                    let i = rng.random_range(0..n);
+                    assert_eq!(s.remove_at(i), v.remove(i));
+                }
+                3 => {
+                    let l = rng.random_range(0..=n);
+                    let r = rng.random_range(l..=n);
+                    s.reverse(l..r);
+                    v[l..r].reverse();
+                }
+                _ => {
+                    let l = rng.random_range(0..=n);
+                    let r = rng.random_range(l..=n);
+                    assert_eq!(s.query(l..r), v[l..r].concat());
+                }
+            }
+            assert_eq!(s.len(), v.len());
+        }
+        assert!(s.iter().eq(v.iter()));
+        assert!((0..v.len()).all(|i| s.get(i) == Some(&v[i])));
+        // past the end, indexed updates panic the way `Vec` does
+        let oob = |r: std::thread::Result<()>| {
+            r.unwrap_err().downcast_ref::<&str>() == Some(&"index out of range")
+        };
+        let n = v.len();
+        assert!(oob(catch_unwind(AssertUnwindSafe(|| drop(s.remove_at(n))))));
+        assert!(oob(catch_unwind(AssertUnwindSafe(|| drop(s.set(n, String::new()))))));
+        assert!(oob(catch_unwind(AssertUnwindSafe(|| s.insert_at(n + 1, String::new())))));
+
+        let (a, b) = s.split_at(v.len() / 3);
+        let (x, y) = v.split_at(v.len() / 3);
+        assert!(a.iter().eq(x.iter()));
+        assert!(b.iter().eq(y.iter()));
+        let s = b.concat(a);
+        assert_eq!(s.query(..), y.concat() + &x.concat());
+
+        let mut t: TreapSeq<char> = "hello world".chars().collect();
+        t.reverse(..5);
+        t.set(5, '_');
+        assert_eq!(t.iter().collect::<String>(), "olleh_world");
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut rb = RBTreeMap::new();
+        let mut treap = TreapMap::new();
+        let mut splay = SplayTreeMap::new();
+        let mut skip = SkipListMap::new();
+        let mut heap = LeftistTree::new();
+
+        for i in 0..3000 {
+            let k = rand::random::<i32>() % 1000;
+            rb.insert(k, i);
+            treap.insert(k, i);
+            splay.insert(k, i);
+            skip.insert(k, i);
+            heap.push(k);
+            if i % 3 == 0 {
+                let k = rand::random::<i32>() % 1000;
+                rb.remove(&k);
+                splay.remove(&k);
+                skip.remove(&k);
+                heap.pop();
+            }
+        }
+        assert_eq!(rb.len(), skip.len());
+        assert_eq!(rb.len(), splay.len());
+        assert_eq!(heap.len(), 2000);
+
+        assert_eq!(rb.validate(), Ok(()));
+        assert_eq!(treap.validate(), Ok(()));
+        assert_eq!(splay.validate(), Ok(()));
+        assert_eq!(skip.validate(), Ok(()));
+        assert_eq!(heap.validate(), Ok(()));
+
+        // one corrupted node at a time, each must be reported as such
+        use crate::trees::binary_tree::{BinaryTree, BinaryTreeNode};
+        use crate::trees::rbtree::Color;
+
+        let mut rb = RBTreeMap::new();
+        (0..100).for_each(|i| rb.insert(i, ()));
+        let root = unsafe { &mut *rb.root().unwrap().as_ptr() };
+        let right = unsafe { &mut *root.right().unwrap().as_ptr() };
+        right.color = Color::Red;
+        assert_eq!(rb.validate(), Err(InvariantError::RedRightLink));
+        right.color = Color::Black;
+        root.color = Color::Red;
+        assert_eq!(rb.validate(), Err(InvariantError::RedRoot));
+        root.color = Color::Black;
+        // a left link may be either colour, flipping it breaks one rule or the other
+        let left = unsafe { &mut *root.left().unwrap().as_ptr() };
+        let flip = |c: &Color| if *c == Color::Red { Color::Black } else { Color::Red };
+        left.color = flip(&left.color);
+        let e = rb.validate();
+        assert!(matches!(e, Err(InvariantError::DoubleRed | InvariantError::BlackHeight)), "{:?}", e);
+        left.color = flip(&left.color);
+        root.size += 1;
+        assert_eq!(rb.validate(), Err(InvariantError::Size { expected: 100, found: 101 }));
+        root.size -= 1;
+        assert_eq!(rb.validate(), Ok(()));
+
+        let root = unsafe { &mut *treap.root().unwrap().as_ptr() };
+        let child = root.left().or(*root.right()).unwrap();
+        let child = unsafe { &mut *child.as_ptr() };
+        std::mem::swap(&mut root.pri, &mut child.pri);
+        assert_eq!(treap.validate(), Err(InvariantError::Priority));
+
+        skip.head_spans[0] += 1;
+        assert_eq!(skip.validate(), Err(InvariantError::Span { level: 0 }));
+
+        heap.root.as_mut().unwrap().dist += 1;
+        assert_eq!(heap.validate(), Err(InvariantError::Dist));
     }
 
     #[test]
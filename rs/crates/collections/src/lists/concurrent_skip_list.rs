@@ -0,0 +1,324 @@
+use super::skip_list::{MAX_LEVEL, P, random_level};
+use crate::misc::rng::with_local_rng;
+use crossbeam_epoch::{self as epoch, Atomic, Owned, Shared};
+use std::{
+    cmp::Ordering,
+    collections::HashSet,
+    marker::PhantomData,
+    sync::atomic::{
+        AtomicUsize,
+        Ordering::{AcqRel, Acquire, Relaxed},
+    },
+};
+
+pub use crossbeam_epoch::{Guard, pin};
+
+/// Lock-free ordered map, a Herlihy-Shavit skip list with epoch-based
+/// reclamation.
+///
+/// A node is removed by tagging its `nexts` links from the top level down,
+/// the tag on level 0 decides the winner. Any search that meets a tagged
+/// node unlinks it. `refs` counts the levels a node is linked on plus one
+/// for a running insert, and the node is retired when it drops to zero.
+pub struct ConcurrentSkipListMap<K, V> {
+    heads: Vec<Atomic<Node<K, V>>>,
+    len: AtomicUsize,
+}
+
+struct Node<K, V> {
+    key: K,
+    val: V,
+    refs: AtomicUsize,
+    nexts: Box<[Atomic<Node<K, V>>]>,
+}
+
+/// The tower of the last node before the key, and its successor, per level.
+type Window<'g, K, V> = (
+    [&'g [Atomic<Node<K, V>>]; MAX_LEVEL],
+    [Shared<'g, Node<K, V>>; MAX_LEVEL],
+);
+
+impl<K: Ord, V> Default for ConcurrentSkipListMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Ord, V> ConcurrentSkipListMap<K, V> {
+    pub fn new() -> Self {
+        Self {
+            heads: (0..MAX_LEVEL).map(|_| Atomic::null()).collect(),
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    /// Number of entries, only a snapshot while other threads write.
+    pub fn len(&self) -> usize {
+        self.len.load(Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Looks `key` up without unlinking anything.
+    pub fn get<'g>(&'g self, key: &K, guard: &'g Guard) -> Option<&'g V> {
+        let mut pred = &self.heads[..];
+        for h in (0..MAX_LEVEL).rev() {
+            let mut curr = pred[h].load(Acquire, guard);
+            while let Some(c) = unsafe { curr.as_ref() } {
+                match c.key.cmp(key) {
+                    Ordering::Less => {
+                        pred = &c.nexts;
+                        curr = c.nexts[h].load(Acquire, guard).with_tag(0);
+                    }
+                    Ordering::Equal => {
+                        let next = c.nexts[0].load(Acquire, guard);
+                        if next.tag() == 0 {
+                            return Some(&c.val);
+                        }
+                        // a removed node may linger on upper levels while a
+                        // newer one with the key is already linked below
+                        if h > 0 {
+                            break;
+                        }
+                        pred = &c.nexts;
+                        curr = next.with_tag(0);
+                    }
+                    Ordering::Greater => break,
+                }
+            }
+        }
+        None
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get(key, &epoch::pin()).is_some()
+    }
+
+    /// Iterates from the first entry for which `before` is false, `before`
+    /// must hold for a prefix of the keys. Consistency as for [`Self::iter`].
+    pub fn seek<'g, F: Fn(&K) -> bool>(&'g self, before: F, guard: &'g Guard) -> Iter<'g, K, V> {
+        let mut pred = &self.heads[..];
+        for h in (0..MAX_LEVEL).rev() {
+            let mut curr = pred[h].load(Acquire, guard);
+            while let Some(c) = unsafe { curr.as_ref() } {
+                if !before(&c.key) {
+                    break;
+                }
+                pred = &c.nexts;
+                curr = c.nexts[h].load(Acquire, guard).with_tag(0);
+            }
+        }
+        Iter {
+            curr: pred[0].load(Acquire, guard).with_tag(0),
+            guard,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Weakly consistent iteration: sees every entry present for the whole
+    /// walk, and may or may not see concurrent changes.
+    pub fn iter<'g>(&'g self, guard: &'g Guard) -> Iter<'g, K, V> {
+        Iter {
+            curr: self.heads[0].load(Acquire, guard),
+            guard,
+            _marker: PhantomData,
+        }
+    }
+}
+
+/// Unlinked nodes are destroyed by the epoch collector, possibly on another
+/// thread and after the map itself is gone, hence the bounds.
+impl<K: Ord + Send + 'static, V: Send + 'static> ConcurrentSkipListMap<K, V> {
+    /// Finds the window around `key` on every level, unlinking tagged nodes
+    /// on the way. Returns whether an unremoved node holds `key`.
+    fn find<'g>(&'g self, key: &K, guard: &'g Guard) -> (bool, Window<'g, K, V>) {
+        'retry: loop {
+            let mut preds = [&self.heads[..]; MAX_LEVEL];
+            let mut succs = [Shared::null(); MAX_LEVEL];
+            let mut pred = &self.heads[..];
+
+            for h in (0..MAX_LEVEL).rev() {
+                let mut curr = pred[h].load(Acquire, guard);
+                if curr.tag() == 1 {
+                    // `pred` itself got removed
+                    continue 'retry;
+                }
+                while let Some(c) = unsafe { curr.as_ref() } {
+                    let succ = c.nexts[h].load(Acquire, guard);
+                    if succ.tag() == 1 {
+                        let succ = succ.with_tag(0);
+                        if pred[h]
+                            .compare_exchange(curr, succ, AcqRel, Acquire, guard)
+                            .is_err()
+                        {
+                            continue 'retry;
+                        }
+                        unsafe { Self::release(curr, guard) };
+                        curr = succ;
+                    } else if c.key < *key {
+                        pred = &c.nexts;
+                        curr = succ;
+                    } else {
+                        break;
+                    }
+                }
+                preds[h] = pred;
+                succs[h] = curr;
+            }
+
+            let found = unsafe { succs[0].as_ref() }.is_some_and(|n| n.key == *key);
+            return (found, (preds, succs));
+        }
+    }
+
+    /// Drops one reference, retiring the node on the last one.
+    unsafe fn release(node: Shared<'_, Node<K, V>>, guard: &Guard) {
+        unsafe {
+            if node.deref().refs.fetch_sub(1, AcqRel) == 1 {
+                guard.defer_destroy(node);
+            }
+        }
+    }
+
+    /// Inserts if `key` is absent, otherwise drops `val` and returns `false`.
+    pub fn insert(&self, key: K, val: V) -> bool {
+        let guard = &epoch::pin();
+        let height = with_local_rng(|rng| random_level(rng, P, MAX_LEVEL));
+        let mut node = Owned::new(Node {
+            key,
+            val,
+            // level 0 and the running insert
+            refs: AtomicUsize::new(2),
+            nexts: (0..height).map(|_| Atomic::null()).collect(),
+        });
+
+        let (p, mut preds, mut succs) = loop {
+            let (found, (preds, succs)) = self.find(&node.key, guard);
+            if found {
+                return false;
+            }
+            for (next, &succ) in node.nexts.iter().zip(&succs) {
+                next.store(succ, Relaxed);
+            }
+            match preds[0][0].compare_exchange(succs[0], node, AcqRel, Acquire, guard) {
+                Ok(p) => break (p, preds, succs),
+                Err(e) => node = e.new,
+            }
+        };
+        let n = unsafe { p.deref() };
+        self.len.fetch_add(1, Relaxed);
+
+        'build: for h in 1..height {
+            loop {
+                let next = n.nexts[h].load(Acquire, guard);
+                if next.tag() == 1 {
+                    break 'build;
+                }
+                if next != succs[h]
+                    && n.nexts[h]
+                        .compare_exchange(next, succs[h], AcqRel, Acquire, guard)
+                        .is_err()
+                {
+                    break 'build;
+                }
+                n.refs.fetch_add(1, Relaxed);
+                if preds[h][h]
+                    .compare_exchange(succs[h], p, AcqRel, Acquire, guard)
+                    .is_ok()
+                {
+                    break;
+                }
+                // can't reach zero, the insert still holds a reference
+                n.refs.fetch_sub(1, Relaxed);
+                let (_, (ps, ss)) = self.find(&n.key, guard);
+                if ss[0] != p {
+                    break 'build;
+                }
+                (preds, succs) = (ps, ss);
+            }
+        }
+
+        if n.nexts[0].load(Acquire, guard).tag() == 1 {
+            // removed while building, unlink any level linked after that
+            self.find(&n.key, guard);
+        }
+        unsafe { Self::release(p, guard) };
+        true
+    }
+
+    pub fn remove(&self, key: &K) -> bool {
+        let guard = &epoch::pin();
+        let (found, (_, succs)) = self.find(key, guard);
+        if !found {
+            return false;
+        }
+        let n = unsafe { succs[0].deref() };
+
+        for h in (1..n.nexts.len()).rev() {
+            let mut next = n.nexts[h].load(Acquire, guard);
+            while next.tag() == 0 {
+                match n.nexts[h].compare_exchange(next, next.with_tag(1), AcqRel, Acquire, guard) {
+                    Ok(_) => break,
+                    Err(e) => next = e.current,
+                }
+            }
+        }
+
+        let mut next = n.nexts[0].load(Acquire, guard);
+        loop {
+            if next.tag() == 1 {
+                // another thread removed it first
+                return false;
+            }
+            match n.nexts[0].compare_exchange(next, next.with_tag(1), AcqRel, Acquire, guard) {
+                Ok(_) => break,
+                Err(e) => next = e.current,
+            }
+        }
+        self.len.fetch_sub(1, Relaxed);
+        self.find(key, guard);
+        true
+    }
+}
+
+pub struct Iter<'g, K, V> {
+    curr: Shared<'g, Node<K, V>>,
+    guard: &'g Guard,
+    _marker: PhantomData<&'g V>,
+}
+
+impl<'g, K, V> Iterator for Iter<'g, K, V> {
+    type Item = (&'g K, &'g V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(c) = unsafe { self.curr.as_ref() } {
+            let next = c.nexts[0].load(Acquire, self.guard);
+            self.curr = next.with_tag(0);
+            if next.tag() == 0 {
+                return Some((&c.key, &c.val));
+            }
+        }
+        None
+    }
+}
+
+impl<K, V> Drop for ConcurrentSkipListMap<K, V> {
+    fn drop(&mut self) {
+        // nodes unlinked from level 0 may still hang on upper levels,
+        // so free everything reachable from any level exactly once
+        let guard = unsafe { epoch::unprotected() };
+        let mut nodes = HashSet::new();
+        for (h, head) in self.heads.iter().enumerate() {
+            let mut curr = head.load(Relaxed, guard);
+            while let Some(c) = unsafe { curr.as_ref() } {
+                nodes.insert(curr.as_raw());
+                curr = c.nexts[h].load(Relaxed, guard).with_tag(0);
+            }
+        }
+        for p in nodes {
+            unsafe { drop(Owned::from_raw(p as *mut Node<K, V>)) };
+        }
+    }
+}
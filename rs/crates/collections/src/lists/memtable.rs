@@ -0,0 +1,198 @@
+use super::concurrent_skip_list::{ConcurrentSkipListMap, Guard};
+use crossbeam_epoch::unprotected;
+use std::{
+    cmp::Ordering,
+    collections::BTreeSet,
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering::{Acquire, Relaxed, Release}},
+    },
+};
+
+/// A versioned entry, deletes are kept as tombstones.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value<V> {
+    Put(V),
+    Delete,
+}
+
+impl<V> Value<V> {
+    pub fn as_put(&self) -> Option<&V> {
+        match self {
+            Value::Put(v) => Some(v),
+            Value::Delete => None,
+        }
+    }
+}
+
+/// Orders by key, then by sequence number descending, so the newest
+/// version of a key comes first.
+#[derive(Debug, PartialEq, Eq)]
+struct InternalKey<K> {
+    key: K,
+    seq: u64,
+}
+
+impl<K: Ord> Ord for InternalKey<K> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key.cmp(&other.key).then(other.seq.cmp(&self.seq))
+    }
+}
+
+impl<K: Ord> PartialOrd for InternalKey<K> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Multi-versioned skip list for the memtable of an LSM store, on top of
+/// [`ConcurrentSkipListMap`]. It is not a mode of `SkipListMap`, whose
+/// writes need `&mut self` and so could not run beside open snapshots.
+///
+/// Every write is a new entry tagged with a caller-supplied sequence
+/// number, nothing is overwritten or removed. Sequence numbers must be
+/// handed out densely from 1, since [`Self::last_seq`] only moves past a
+/// number once every write at or below it has landed. Writers may finish
+/// out of order, so a snapshot taken at `last_seq` stays stable.
+pub struct MemTable<K, V> {
+    map: ConcurrentSkipListMap<InternalKey<K>, Value<V>>,
+    last_seq: AtomicU64,
+    /// Finished writes above `last_seq`, waiting for the gap below them.
+    pending: Mutex<BTreeSet<u64>>,
+}
+
+impl<K: Ord, V> Default for MemTable<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Ord, V> MemTable<K, V> {
+    pub fn new() -> Self {
+        Self {
+            map: ConcurrentSkipListMap::new(),
+            last_seq: AtomicU64::new(0),
+            pending: Mutex::new(BTreeSet::new()),
+        }
+    }
+
+    /// Number of versions, tombstones included.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Highest sequence number such that it and every one below it have
+    /// been written.
+    pub fn last_seq(&self) -> u64 {
+        self.last_seq.load(Acquire)
+    }
+
+    /// Returns the newest version of `key` written at or below `seq`.
+    pub fn get_at<'g>(&'g self, key: &K, seq: u64, guard: &'g Guard) -> Option<&'g Value<V>> {
+        self.map
+            .seek(
+                |ik| ik.key < *key || (ik.key == *key && ik.seq > seq),
+                guard,
+            )
+            .next()
+            .filter(|(ik, _)| ik.key == *key)
+            .map(|(_, v)| v)
+    }
+
+    /// Every version in `(key, seq)` order, newest first per key, for
+    /// flushing to disk.
+    pub fn versions<'g>(
+        &'g self,
+        guard: &'g Guard,
+    ) -> impl Iterator<Item = (&'g K, u64, &'g Value<V>)> {
+        self.map.iter(guard).map(|(ik, v)| (&ik.key, ik.seq, v))
+    }
+
+    /// Read view at the latest sequence number.
+    pub fn snapshot(&self) -> Snapshot<'_, K, V> {
+        self.snapshot_at(self.last_seq())
+    }
+
+    pub fn snapshot_at(&self, seq: u64) -> Snapshot<'_, K, V> {
+        Snapshot { table: self, seq }
+    }
+}
+
+impl<K: Ord + Send + 'static, V: Send + 'static> MemTable<K, V> {
+    /// Writes `val` for `key` at `seq`, returns `false` if that version exists.
+    pub fn put(&self, key: K, seq: u64, val: V) -> bool {
+        self.write(key, seq, Value::Put(val))
+    }
+
+    /// Writes a tombstone for `key` at `seq`.
+    pub fn delete(&self, key: K, seq: u64) -> bool {
+        self.write(key, seq, Value::Delete)
+    }
+
+    fn write(&self, key: K, seq: u64, val: Value<V>) -> bool {
+        let added = self.map.insert(InternalKey { key, seq }, val);
+        self.complete(seq);
+        added
+    }
+
+    /// Marks `seq` written and advances `last_seq` over the finished prefix.
+    fn complete(&self, seq: u64) {
+        let mut pending = self.pending.lock().unwrap();
+        let mut last = self.last_seq.load(Relaxed);
+        if seq <= last {
+            return;
+        }
+        pending.insert(seq);
+        while pending.remove(&(last + 1)) {
+            last += 1;
+        }
+        // pairs with the load in `last_seq`, so the versions are visible
+        self.last_seq.store(last, Release);
+    }
+}
+
+/// Consistent view of a [`MemTable`] at a sequence number.
+///
+/// Stability comes from the sequence number alone, a snapshot holds no
+/// epoch pin, so keeping one around does not hold back reclamation.
+pub struct Snapshot<'a, K, V> {
+    table: &'a MemTable<K, V>,
+    seq: u64,
+}
+
+impl<'a, K: Ord, V> Snapshot<'a, K, V> {
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Returns the visible value, `None` if absent or deleted.
+    pub fn get(&self, key: &K) -> Option<&'a V> {
+        self.table.get_at(key, self.seq, Self::guard())?.as_put()
+    }
+
+    /// Iterates over the visible live entries in key order.
+    pub fn iter(&self) -> impl Iterator<Item = (&'a K, &'a V)> {
+        let seq = self.seq;
+        let mut last: Option<&K> = None;
+        self.table
+            .versions(Self::guard())
+            .filter_map(move |(k, s, v)| {
+                // the first version at or below the snapshot decides the key
+                if s > seq || last == Some(k) {
+                    return None;
+                }
+                last = Some(k);
+                v.as_put().map(|v| (k, v))
+            })
+    }
+
+    fn guard() -> &'static Guard {
+        // SAFETY: a memtable never removes entries, so no node is retired
+        // while the table is borrowed and reads need no pin
+        unsafe { unprotected() }
+    }
+}
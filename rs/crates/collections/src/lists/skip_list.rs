@@ -1,20 +1,40 @@
 #![allow(dead_code)]
 
 use fmt::Display;
-use std::{marker::PhantomData, ptr::NonNull};
+use crate::invariant::InvariantError;
+use crate::misc::rng::{DefaultRng, default_rng};
+use rand::{Rng, RngCore};
+use std::{
+    collections::HashMap,
+    marker::PhantomData,
+    ops::{Bound, RangeBounds},
+    ptr::NonNull,
+};
 use std::{cmp::Ordering, fmt, fmt::Debug};
 use std::{
     fmt::Formatter,
     mem::{size_of, size_of_val},
 };
 
-const P: f32 = 0.3;
-const MAX_LEVEL: usize = 32;
+pub(crate) const P: f32 = 0.3;
+pub(crate) const MAX_LEVEL: usize = 32;
+
+/// Geometric tower height, shared with the concurrent skip list.
+pub(crate) fn random_level<R: Rng + ?Sized>(rng: &mut R, p: f32, max_level: usize) -> usize {
+    let mut l = 1;
+    while rng.random::<f32>() < p && l < max_level {
+        l += 1;
+    }
+    l
+}
 
 type Link<K, V> = Option<NonNull<Node<K, V>>>;
+/// Per level, the last link before the target, its span slot and the
+/// position of the link owner (the head is 0, the `i`-th node is `i + 1`).
+type Update<K, V> = Vec<(*mut Link<K, V>, *mut usize, usize)>;
 
-pub struct SkipListSet<T> {
-    map: SkipListMap<T, ()>,
+pub struct SkipListSet<T, R: RngCore = DefaultRng> {
+    map: SkipListMap<T, (), R>,
 }
 
 impl<T: Ord> Default for SkipListSet<T> {
@@ -29,6 +49,14 @@ impl<T: Ord> SkipListSet<T> {
             map: SkipListMap::new(),
         }
     }
+}
+
+impl<T: Ord, R: RngCore> SkipListSet<T, R> {
+    pub fn with_config(config: SkipListConfig<R>) -> Self {
+        Self {
+            map: SkipListMap::with_config(config),
+        }
+    }
 
     pub fn insert(&mut self, key: T) -> bool {
         self.map.insert(key, ())
@@ -49,10 +77,68 @@ impl<T: Ord> SkipListSet<T> {
     pub fn remove(&mut self, key: &T) -> bool {
         self.map.remove(key)
     }
+
+    /// Returns the `i`-th smallest element (0-indexed).
+    pub fn nth(&self, i: usize) -> Option<&T> {
+        self.map.nth(i).map(|x| x.0)
+    }
+
+    /// Returns the number of elements strictly less than `key`.
+    pub fn rank(&self, key: &T) -> usize {
+        self.map.rank(key)
+    }
+
+    pub fn remove_at(&mut self, i: usize) -> Option<T> {
+        self.map.remove_at(i).map(|x| x.0)
+    }
+
+    /// Iterates over the elements whose ranks fall into `range`.
+    pub fn range_by_rank<B: RangeBounds<usize>>(&self, range: B) -> impl DoubleEndedIterator<Item = &T> {
+        self.map.range_by_rank(range).map(|x| x.0)
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &T> {
+        self.map.iter().map(|x| x.0)
+    }
+
+    pub fn range<B: RangeBounds<T>>(&self, range: B) -> impl DoubleEndedIterator<Item = &T> {
+        self.map.range(range).map(|x| x.0)
+    }
+
+    pub fn first(&self) -> Option<&T> {
+        self.map.first_key_value().map(|x| x.0)
+    }
+
+    pub fn last(&self) -> Option<&T> {
+        self.map.last_key_value().map(|x| x.0)
+    }
+}
+
+/// How a `SkipListMap` draws tower heights.
+pub struct SkipListConfig<R: RngCore = DefaultRng> {
+    /// Chance that a tower grows one more level.
+    pub p: f32,
+    pub max_level: usize,
+    pub rng: R,
+}
+
+impl Default for SkipListConfig {
+    /// `p = 0.3` and 32 levels, with [`default_rng`].
+    fn default() -> Self {
+        Self {
+            p: P,
+            max_level: MAX_LEVEL,
+            rng: default_rng(),
+        }
+    }
 }
 
-pub struct SkipListMap<K, V> {
+pub struct SkipListMap<K, V, R: RngCore = DefaultRng> {
     heads: Vec<Link<K, V>>,
+    pub(crate) head_spans: Vec<usize>,
+    tail: Link<K, V>,
+    p: f32,
+    rng: R,
     _marker: PhantomData<(K, V)>,
     _len: usize,
     _height: usize,
@@ -60,26 +146,25 @@ pub struct SkipListMap<K, V> {
 
 struct Node<K, V> {
     nexts: Vec<Link<K, V>>,
+    // level-0 predecessor
+    prev: Link<K, V>,
+    // how many level-0 steps `nexts[h]` skips, meaningless for `None`
+    spans: Vec<usize>,
     key: K,
     val: V,
 }
 
 impl<K, V> Node<K, V> {
-    fn new(key: K, val: V) -> Self {
+    fn new(key: K, val: V, level: usize) -> Self {
         Self {
-            nexts: vec![None; Self::random_level()],
+            nexts: vec![None; level],
+            prev: None,
+            spans: vec![0; level],
             key,
             val,
         }
     }
 
-    fn random_level() -> usize {
-        let mut l = 1;
-        while rand::random::<f32>() < P && l < MAX_LEVEL {
-            l += 1;
-        }
-        l
-    }
 }
 
 impl<K: Ord, V> Default for SkipListMap<K, V> {
@@ -90,8 +175,19 @@ impl<K: Ord, V> Default for SkipListMap<K, V> {
 
 impl<K: Ord, V> SkipListMap<K, V> {
     pub fn new() -> Self {
+        Self::with_config(SkipListConfig::default())
+    }
+}
+
+impl<K: Ord, V, R: RngCore> SkipListMap<K, V, R> {
+    pub fn with_config(config: SkipListConfig<R>) -> Self {
+        assert!(config.max_level > 0, "max_level must be positive");
         Self {
-            heads: vec![None; MAX_LEVEL],
+            heads: vec![None; config.max_level],
+            head_spans: vec![0; config.max_level],
+            tail: None,
+            p: config.p,
+            rng: config.rng,
             _len: 0,
             _height: 0,
             _marker: PhantomData
@@ -106,52 +202,71 @@ impl<K: Ord, V> SkipListMap<K, V> {
         self._height
     }
 
+    /// Inserts or overwrites, returns `false` if the key was already present.
     pub fn insert(&mut self, key: K, val: V) -> bool {
-        if let Some(mut nd) = Self::get_node(&self.heads, self._height, &key) {
-            unsafe {
-                nd.as_mut().val = val;
+        match self.entry(key) {
+            Entry::Occupied(mut e) => {
+                e.insert(val);
+                false
             }
-            false
-        } else {
-            self._len += 1;
-            let node = Box::new(Node::new(key, val));
-            self._height = self._height.max(node.nexts.len());
-            unsafe {
-                Self::insert_node(
-                    &mut self.heads,
-                    self._height,
-                    NonNull::new_unchecked(Box::into_raw(node)),
-                );
+            Entry::Vacant(e) => {
+                e.insert(val);
+                true
             }
-            true
         }
     }
 
-    fn insert_node(mut nexts: &mut [Link<K, V>], mut h: usize, mut node: NonNull<Node<K, V>>) {
-        let node_level = unsafe { node.as_ref().nexts.len() };
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V, R> {
+        let (update, found) = self.search(|p, _| p.key.cmp(&key));
+        match found {
+            Some(p) => Entry::Occupied(OccupiedEntry::new(p)),
+            None => Entry::Vacant(VacantEntry {
+                key,
+                update,
+                map: self,
+            }),
+        }
+    }
 
-        while h > 0 {
-            if let Some(p) = nexts[h - 1] {
-                unsafe {
-                    match node.as_ref().key.cmp(&p.as_ref().key) {
-                        Ordering::Equal => panic!(),
+    /// Descends the tower. `cmp` gets a candidate node with its position and
+    /// tells where it lies relative to the target. Levels above the current
+    /// height are linked from the heads.
+    fn search<F>(&mut self, mut cmp: F) -> (Update<K, V>, Link<K, V>)
+    where
+        F: FnMut(&Node<K, V>, usize) -> Ordering,
+    {
+        let mut update: Update<K, V> = self
+            .heads
+            .iter_mut()
+            .zip(self.head_spans.iter_mut())
+            .map(|(x, s)| (x as *mut _, s as *mut _, 0))
+            .collect();
+        let mut nexts: *mut Vec<Link<K, V>> = &mut self.heads;
+        let mut spans: *mut Vec<usize> = &mut self.head_spans;
+        let mut pos = 0;
+        let mut found = None;
+
+        for h in (0..self._height).rev() {
+            unsafe {
+                while let Some(p) = (&*nexts)[h] {
+                    let next_pos = pos + (&*spans)[h];
+                    match cmp(p.as_ref(), next_pos) {
                         Ordering::Less => {
-                            if h <= node_level {
-                                node.as_mut().nexts[h - 1].replace(p);
-                                nexts[h - 1].replace(node);
-                            }
-                            h -= 1;
+                            pos = next_pos;
+                            nexts = &mut (*p.as_ptr()).nexts;
+                            spans = &mut (*p.as_ptr()).spans;
+                        }
+                        Ordering::Equal => {
+                            found = Some(p);
+                            break;
                         }
-                        Ordering::Greater => nexts = &mut (*p.as_ptr()).nexts,
+                        Ordering::Greater => break,
                     }
                 }
-            } else {
-                if h <= node_level {
-                    nexts[h - 1].replace(node);
-                }
-                h -= 1;
+                update[h] = (&mut (&mut *nexts)[h], &mut (&mut *spans)[h], pos);
             }
         }
+        (update, found)
     }
 
     pub fn get(&self, key: &K) -> Option<&V> {
@@ -173,38 +288,296 @@ impl<K: Ord, V> SkipListMap<K, V> {
         None
     }
 
-    fn remove_node(mut nexts: &mut [Link<K, V>], mut h: usize, key: &K) -> Option<Box<Node<K, V>>> {
-        let mut to_remove = None;
-        while h > 0 {
-            if let Some(p) = nexts[h - 1] {
-                match key.cmp(unsafe { &p.as_ref().key }) {
-                    Ordering::Equal => unsafe {
-                        to_remove.replace(p);
-                        nexts[h - 1] = p.as_ref().nexts[h - 1];
-                        h -= 1;
-                    },
-                    Ordering::Less => h -= 1,
-                    Ordering::Greater => nexts = unsafe { &mut (*p.as_ptr()).nexts },
+    /// Unlinks the node found by `search`, fixing the spans on every level.
+    fn unlink(&mut self, update: Update<K, V>, node: NonNull<Node<K, V>>) -> (K, V) {
+        unsafe {
+            for (h, &(link, span, _)) in update[..self._height].iter().enumerate() {
+                if *link == Some(node) {
+                    *link = node.as_ref().nexts[h];
+                    *span = *span + node.as_ref().spans[h] - 1;
+                } else if (*link).is_some() {
+                    *span -= 1;
                 }
-            } else{
-                h -= 1;
             }
+            match node.as_ref().nexts[0] {
+                Some(mut n) => n.as_mut().prev = node.as_ref().prev,
+                None => self.tail = node.as_ref().prev,
+            }
+            self._len -= 1;
+            debug_invariants!(self);
+            let b = Box::from_raw(node.as_ptr());
+            (b.key, b.val)
         }
-        to_remove.map(|x| unsafe { Box::from_raw(x.as_ptr()) })
     }
 
     pub fn remove(&mut self, key: &K) -> bool {
-        if self.get(key).is_none() {
-            false
-        } else {
-            let n = self._height;
-            Self::remove_node(&mut self.heads, n, key);
-            true
+        self.remove_entry(key).is_some()
+    }
+
+    pub fn remove_entry(&mut self, key: &K) -> Option<(K, V)> {
+        let (update, found) = self.search(|p, _| p.key.cmp(key));
+        found.map(|p| self.unlink(update, p))
+    }
+
+    /// Removes the `i`-th smallest entry (0-indexed).
+    pub fn remove_at(&mut self, i: usize) -> Option<(K, V)> {
+        let (update, found) = self.search(|_, pos| pos.cmp(&(i + 1)));
+        found.map(|p| self.unlink(update, p))
+    }
+
+    /// Returns the `i`-th smallest entry (0-indexed).
+    pub fn nth(&self, i: usize) -> Option<(&K, &V)> {
+        let p = self.nth_node(i)?;
+        unsafe { Some((&(*p.as_ptr()).key, &(*p.as_ptr()).val)) }
+    }
+
+    fn nth_node(&self, i: usize) -> Link<K, V> {
+        if i >= self._len {
+            return None;
+        }
+        let mut nexts = &self.heads;
+        let mut spans = &self.head_spans;
+        let mut pos = 0;
+        let mut cur = None;
+        for h in (0..self._height).rev() {
+            while let Some(p) = nexts[h] {
+                if pos + spans[h] > i + 1 {
+                    break;
+                }
+                pos += spans[h];
+                cur = Some(p);
+                unsafe {
+                    nexts = &(*p.as_ptr()).nexts;
+                    spans = &(*p.as_ptr()).spans;
+                }
+            }
+            if pos == i + 1 {
+                break;
+            }
+        }
+        cur
+    }
+
+    /// Returns the number of keys strictly less than `key`.
+    pub fn rank(&self, key: &K) -> usize {
+        self.seek(|k| k < key).1
+    }
+
+    /// Finds the last node whose key satisfies `before` with the tower,
+    /// along with its position. `before` must hold for a prefix of the keys.
+    fn seek<F: Fn(&K) -> bool>(&self, before: F) -> (Link<K, V>, usize) {
+        let mut nexts = &self.heads;
+        let mut spans = &self.head_spans;
+        let mut pos = 0;
+        let mut cur = None;
+        for h in (0..self._height).rev() {
+            while let Some(p) = nexts[h] {
+                let b = unsafe { &*p.as_ptr() };
+                if !before(&b.key) {
+                    break;
+                }
+                pos += spans[h];
+                cur = Some(p);
+                nexts = &b.nexts;
+                spans = &b.spans;
+            }
+        }
+        (cur, pos)
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            raw: RawIter {
+                front: self.heads[0],
+                back: self.tail,
+                len: self._len,
+            },
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V> {
+        IterMut {
+            raw: self.iter().raw,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Iterates over the entries whose keys fall into `range`, from either
+    /// end. Both ends are found with the tower.
+    pub fn range<B: RangeBounds<K>>(&self, range: B) -> Iter<'_, K, V> {
+        let (before, at) = match range.start_bound() {
+            Bound::Included(lo) => self.seek(|k| k < lo),
+            Bound::Excluded(lo) => self.seek(|k| k <= lo),
+            Bound::Unbounded => (None, 0),
+        };
+        let front = match before {
+            Some(p) => unsafe { p.as_ref().nexts[0] },
+            None => self.heads[0],
+        };
+        let (back, end) = match range.end_bound() {
+            Bound::Included(hi) => self.seek(|k| k <= hi),
+            Bound::Excluded(hi) => self.seek(|k| k < hi),
+            Bound::Unbounded => (self.tail, self._len),
+        };
+        Iter {
+            raw: RawIter {
+                front,
+                back,
+                len: end.saturating_sub(at),
+            },
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn first_key_value(&self) -> Option<(&K, &V)> {
+        self.iter().next()
+    }
+
+    pub fn last_key_value(&self) -> Option<(&K, &V)> {
+        self.iter().next_back()
+    }
+
+    /// Iterates over the entries whose ranks fall into `range`.
+    pub fn range_by_rank<B: RangeBounds<usize>>(&self, range: B) -> Iter<'_, K, V> {
+        let lo = match range.start_bound() {
+            Bound::Included(&i) => i,
+            Bound::Excluded(&i) => i + 1,
+            Bound::Unbounded => 0,
+        };
+        let hi = match range.end_bound() {
+            Bound::Included(&i) => i + 1,
+            Bound::Excluded(&i) => i,
+            Bound::Unbounded => self._len,
+        };
+        let hi = hi.min(self._len);
+        Iter {
+            raw: RawIter {
+                front: self.nth_node(lo),
+                back: hi.checked_sub(1).and_then(|i| self.nth_node(i)),
+                len: hi.saturating_sub(lo),
+            },
+            _marker: PhantomData,
+        }
+    }
+
+    /// Checks that every level is sorted and holds exactly the nodes of the
+    /// level below that are tall enough, and that the length is right.
+    pub fn validate(&self) -> Result<(), InvariantError> {
+        if let Some(h) = (self._height..self.heads.len()).find(|&h| self.heads[h].is_some()) {
+            return Err(InvariantError::Level { level: h });
+        }
+
+        for h in 0..self._height {
+            let mut node = self.heads[h];
+            // the next node expected on the level above
+            let mut upper = self.heads.get(h + 1).copied().flatten();
+            let mut last: Option<&K> = None;
+
+            while let Some(p) = node {
+                let p = unsafe { &*p.as_ptr() };
+                if last.is_some_and(|k| k >= &p.key) {
+                    return Err(InvariantError::Order);
+                }
+                if p.nexts.len() > h + 1 {
+                    if upper.map(|x| x.as_ptr() as *const _) != Some(p as *const _) {
+                        return Err(InvariantError::Level { level: h + 1 });
+                    }
+                    upper = p.nexts[h + 1];
+                }
+                last = Some(&p.key);
+                node = p.nexts[h];
+            }
+            if upper.is_some() {
+                return Err(InvariantError::Level { level: h + 1 });
+            }
+        }
+
+        let mut cnt = 0;
+        let mut pos = HashMap::new();
+        let (mut prev, mut node) = (None, self.heads[0]);
+        while let Some(p) = node {
+            if unsafe { p.as_ref().prev } != prev {
+                return Err(InvariantError::Level { level: 0 });
+            }
+            cnt += 1;
+            pos.insert(p, cnt);
+            (prev, node) = (node, unsafe { p.as_ref().nexts[0] });
+        }
+        if prev != self.tail {
+            return Err(InvariantError::Level { level: 0 });
+        }
+        if cnt != self._len {
+            return Err(InvariantError::Size { expected: cnt, found: self._len });
+        }
+
+        for h in 0..self._height {
+            let (mut at, mut node, mut span) = (0, self.heads[h], self.head_spans[h]);
+            while let Some(p) = node {
+                if at + span != pos[&p] {
+                    return Err(InvariantError::Span { level: h });
+                }
+                let p_ref = unsafe { p.as_ref() };
+                (at, node, span) = (pos[&p], p_ref.nexts[h], p_ref.spans[h]);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl_entry!(R: RngCore = DefaultRng);
+
+pub struct VacantEntry<'a, K, V, R: RngCore = DefaultRng> {
+    key: K,
+    // the link to patch on every level
+    update: Update<K, V>,
+    map: &'a mut SkipListMap<K, V, R>,
+}
+
+impl<'a, K: Ord, V, R: RngCore> VacantEntry<'a, K, V, R> {
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    pub fn into_key(self) -> K {
+        self.key
+    }
+
+    pub fn insert(self, val: V) -> &'a mut V {
+        let level = random_level(&mut self.map.rng, self.map.p, self.map.heads.len());
+        let mut node = NonNull::from(Box::leak(Box::new(Node::new(self.key, val, level))));
+        unsafe {
+            let level = node.as_ref().nexts.len();
+            let pos = self.update[0].2 + 1;
+            for (h, &(link, span, owner)) in self.update.iter().enumerate() {
+                if h < level {
+                    // the old successor moves one step further away
+                    node.as_mut().spans[h] = (owner + *span + 1).saturating_sub(pos);
+                    node.as_mut().nexts[h] = (*link).replace(node);
+                    *span = pos - owner;
+                } else if (*link).is_some() {
+                    *span += 1;
+                }
+            }
+            match node.as_ref().nexts[0] {
+                Some(mut n) => {
+                    node.as_mut().prev = n.as_ref().prev;
+                    n.as_mut().prev = Some(node);
+                }
+                None => {
+                    node.as_mut().prev = self.map.tail;
+                    self.map.tail = Some(node);
+                }
+            }
+            self.map._len += 1;
+            self.map._height = self.map._height.max(level);
+            debug_invariants!(self.map);
+            &mut (*node.as_ptr()).val
         }
     }
 }
 
-impl<K, V> Drop for SkipListMap<K, V> {
+impl<K, V, R: RngCore> Drop for SkipListMap<K, V, R> {
     fn drop(&mut self) {
         let mut node = self.heads[0];
         while let Some(p) = node {
@@ -216,7 +589,7 @@ impl<K, V> Drop for SkipListMap<K, V> {
     }
 }
 
-impl<K: Debug, V: Debug> Debug for SkipListMap<K, V> {
+impl<K: Debug, V: Debug, R: RngCore> Debug for SkipListMap<K, V, R> {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         for i in (0..self._height).rev() {
             let mut node = self.heads[i];
@@ -242,56 +615,158 @@ impl<K: Debug, V: Debug> Debug for SkipListMap<K, V> {
     }
 }
 
-impl<T: Debug> fmt::Debug for SkipListSet<T> {
+impl<T: Debug, R: RngCore> fmt::Debug for SkipListSet<T, R> {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         write!(f, "{:?}", self.map)
     }
 }
 
+/// Level-0 cursor shared by the iterators, `len` counts the nodes left
+/// between `front` and `back` inclusive.
+struct RawIter<K, V> {
+    front: Link<K, V>,
+    back: Link<K, V>,
+    len: usize,
+}
+
+impl<K, V> RawIter<K, V> {
+    fn next(&mut self) -> Link<K, V> {
+        if self.len == 0 {
+            return None;
+        }
+        let p = self.front?;
+        self.len -= 1;
+        self.front = unsafe { p.as_ref().nexts[0] };
+        Some(p)
+    }
+
+    fn next_back(&mut self) -> Link<K, V> {
+        if self.len == 0 {
+            return None;
+        }
+        let p = self.back?;
+        self.len -= 1;
+        self.back = unsafe { p.as_ref().prev };
+        Some(p)
+    }
+}
+
 pub struct Iter<'a, K, V> {
-    pointer: Link<K, V>,
+    raw: RawIter<K, V>,
     _marker: PhantomData<&'a Node<K, V>>,
 }
 
+pub struct IterMut<'a, K, V> {
+    raw: RawIter<K, V>,
+    _marker: PhantomData<&'a mut Node<K, V>>,
+}
+
 pub struct IntoIter<K, V> {
-    pointer: Link<K, V>,
+    raw: RawIter<K, V>,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.raw.next().map(|x| unsafe { (&(*x.as_ptr()).key, &(*x.as_ptr()).val) })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.raw.len, Some(self.raw.len))
+    }
 }
 
+impl<K, V> DoubleEndedIterator for Iter<'_, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.raw.next_back().map(|x| unsafe { (&(*x.as_ptr()).key, &(*x.as_ptr()).val) })
+    }
+}
+
+impl<K, V> ExactSizeIterator for Iter<'_, K, V> {}
+
+impl<'a, K, V> Iterator for IterMut<'a, K, V> {
+    type Item = (&'a K, &'a mut V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.raw.next().map(|x| unsafe { (&(*x.as_ptr()).key, &mut (*x.as_ptr()).val) })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.raw.len, Some(self.raw.len))
+    }
+}
+
+impl<K, V> DoubleEndedIterator for IterMut<'_, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.raw.next_back().map(|x| unsafe { (&(*x.as_ptr()).key, &mut (*x.as_ptr()).val) })
+    }
+}
+
+impl<K, V> ExactSizeIterator for IterMut<'_, K, V> {}
+
 impl<K, V> Iterator for IntoIter<K, V> {
     type Item = (K, V);
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.pointer.map(|x| unsafe {
+        self.raw.next().map(|x| unsafe {
             let b = Box::from_raw(x.as_ptr());
-            let output = (b.key, b.val);
-            self.pointer = b.nexts[0];
-            output
+            (b.key, b.val)
         })
     }
-}
 
-impl<'a, K, V> Iterator for Iter<'a, K, V> {
-    type Item = (&'a K, &'a V);
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.raw.len, Some(self.raw.len))
+    }
+}
 
-    fn next(&mut self) -> Option<Self::Item> {
-        self.pointer.map(|x| unsafe {
-            let b = x.as_ptr();
-            let output = (&(*b).key, &(*b).val);
-            self.pointer = (*b).nexts[0];
-            output
+impl<K, V> DoubleEndedIterator for IntoIter<K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.raw.next_back().map(|x| unsafe {
+            let b = Box::from_raw(x.as_ptr());
+            (b.key, b.val)
         })
     }
 }
 
+impl<K, V> ExactSizeIterator for IntoIter<K, V> {}
 
-impl<K, V> IntoIterator for SkipListMap<K, V> {
+impl<K, V> Drop for IntoIter<K, V> {
+    fn drop(&mut self) {
+        for _ in self.by_ref() {}
+    }
+}
+
+impl<K, V, R: RngCore> IntoIterator for SkipListMap<K, V, R> {
     type Item = (K, V);
     type IntoIter = IntoIter<K, V>;
 
     fn into_iter(mut self) -> Self::IntoIter {
-        IntoIter {
-            pointer: self.heads[0].take(),
-        }
+        let raw = RawIter {
+            front: self.heads[0],
+            back: self.tail,
+            len: self._len,
+        };
+        // the iterator owns the nodes now
+        self.heads.iter_mut().for_each(|x| *x = None);
+        IntoIter { raw }
     }
 }
 
+impl<'a, K: Ord, V, R: RngCore> IntoIterator for &'a SkipListMap<K, V, R> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, K: Ord, V, R: RngCore> IntoIterator for &'a mut SkipListMap<K, V, R> {
+    type Item = (&'a K, &'a mut V);
+    type IntoIter = IterMut<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
@@ -0,0 +1,38 @@
+use rand::{SeedableRng, rngs::StdRng};
+use std::cell::RefCell;
+
+/// Rng the randomized structures use when not given one.
+pub type DefaultRng = StdRng;
+
+thread_local! {
+    static SEEDER: RefCell<Option<StdRng>> = const { RefCell::new(None) };
+    static LOCAL: RefCell<Option<StdRng>> = const { RefCell::new(None) };
+}
+
+/// Seeds the rngs of every structure created afterwards on this thread, so
+/// the same seed and the same operations build the same shapes.
+pub fn set_seed(seed: u64) {
+    SEEDER.with_borrow_mut(|s| *s = Some(StdRng::seed_from_u64(seed)));
+    LOCAL.with_borrow_mut(|r| *r = None);
+}
+
+/// Goes back to seeding from the thread rng.
+pub fn clear_seed() {
+    SEEDER.with_borrow_mut(|s| *s = None);
+    LOCAL.with_borrow_mut(|r| *r = None);
+}
+
+/// A fresh rng, drawn from the seed set by [`set_seed`] if there is one.
+pub fn default_rng() -> DefaultRng {
+    SEEDER.with_borrow_mut(|s| match s {
+        Some(s) => StdRng::from_rng(s),
+        None => StdRng::from_rng(&mut rand::rng()),
+    })
+}
+
+/// Runs `f` with this thread's rng, for structures shared across threads
+/// that cannot own one. It is drawn from [`default_rng`] on first use and
+/// redrawn after [`set_seed`] or [`clear_seed`].
+pub fn with_local_rng<T>(f: impl FnOnce(&mut DefaultRng) -> T) -> T {
+    LOCAL.with_borrow_mut(|r| f(r.get_or_insert_with(default_rng)))
+}
@@ -1,6 +1,11 @@
 #![macro_use]
 
-use std::{fmt, marker::PhantomData, ptr::NonNull};
+use std::{
+    fmt,
+    marker::PhantomData,
+    ops::{Bound, RangeBounds},
+    ptr::NonNull,
+};
 
 pub trait BinaryTree {
     type Node: BinaryTreeNode;
@@ -45,6 +50,16 @@ pub struct Iter<'a, N: BinaryTreeNode> {
     _marker: PhantomData<&'a N>,
 }
 
+/// In-order iterator over the entries whose keys fall into a range.
+///
+/// `front` and `back` hold the unvisited part of the search paths towards the
+/// lower and the upper bound, so both ends advance in amortized O(1).
+pub struct Range<'a, N: BinaryTreeNode> {
+    front: Vec<NonNull<N>>,
+    back: Vec<NonNull<N>>,
+    _marker: PhantomData<&'a N>,
+}
+
 pub struct IntoIter<N: BinaryTreeNode> {
     stack: Vec<NonNull<N>>,
     _marker: PhantomData<Box<N>>,
@@ -71,6 +86,106 @@ impl<'a, N: BinaryTreeNode> Iterator for Iter<'a, N> {
     }
 }
 
+impl<'a, N: BinaryTreeNode> Range<'a, N>
+where
+    N::Key: Ord,
+{
+    /// `root` must stay valid and unmodified for `'a`.
+    pub(crate) fn new<R: RangeBounds<N::Key>>(root: Option<NonNull<N>>, range: R) -> Self {
+        let mut front = vec![];
+        let mut cur = root;
+        while let Some(x) = cur {
+            let p = unsafe { x.as_ref() };
+            let k = p.kv().0;
+            let in_range = match range.start_bound() {
+                Bound::Included(s) => k >= s,
+                Bound::Excluded(s) => k > s,
+                Bound::Unbounded => true,
+            };
+            if in_range {
+                front.push(x);
+                cur = *p.left();
+            } else {
+                cur = *p.right();
+            }
+        }
+
+        let mut back = vec![];
+        let mut cur = root;
+        while let Some(x) = cur {
+            let p = unsafe { x.as_ref() };
+            let k = p.kv().0;
+            let in_range = match range.end_bound() {
+                Bound::Included(e) => k <= e,
+                Bound::Excluded(e) => k < e,
+                Bound::Unbounded => true,
+            };
+            if in_range {
+                back.push(x);
+                cur = *p.right();
+            } else {
+                cur = *p.left();
+            }
+        }
+
+        Self {
+            front,
+            back,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Both ends stop once they would cross each other.
+    fn is_exhausted(&self) -> bool {
+        match (self.front.last(), self.back.last()) {
+            (Some(f), Some(b)) => unsafe { f.as_ref().kv().0 > b.as_ref().kv().0 },
+            _ => true,
+        }
+    }
+}
+
+impl<'a, N: BinaryTreeNode> Iterator for Range<'a, N>
+where
+    N::Key: Ord,
+{
+    type Item = (&'a N::Key, &'a N::Value);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.is_exhausted() {
+            return None;
+        }
+        let last = self.front.pop()?;
+        unsafe {
+            let mut cur = *last.as_ref().right();
+            while let Some(x) = cur {
+                self.front.push(x);
+                cur = *x.as_ref().left();
+            }
+            Some((*last.as_ptr()).kv())
+        }
+    }
+}
+
+impl<'a, N: BinaryTreeNode> DoubleEndedIterator for Range<'a, N>
+where
+    N::Key: Ord,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.is_exhausted() {
+            return None;
+        }
+        let last = self.back.pop()?;
+        unsafe {
+            let mut cur = *last.as_ref().left();
+            while let Some(x) = cur {
+                self.back.push(x);
+                cur = *x.as_ref().right();
+            }
+            Some((*last.as_ptr()).kv())
+        }
+    }
+}
+
 impl<N: BinaryTreeNode> Iterator for IntoIter<N> {
     type Item = (N::Key, N::Value);
 
@@ -97,12 +212,18 @@ macro_rules! impl_iter {
 }
 
 macro_rules! impl_debug {
-    ($T: tt) => {
-        impl<K: std::fmt::Debug, V: std::fmt::Debug> std::fmt::Debug for $T<K, V> {
+    ($T: tt $(, $R: ident: $B: path)*) => {
+        impl_debug!($T, |t: &Self| t.root $(, $R: $B)*);
+    };
+
+    // `$root` reads the root link, for trees that don't store it directly;
+    // `$R: $B` are the tree's extra type parameters after `K, V`
+    ($T: tt, $root: expr $(, $R: ident: $B: path)*) => {
+        impl<K: std::fmt::Debug, V: std::fmt::Debug $(, $R: $B)*> std::fmt::Debug for $T<K, V $(, $R)*> {
             fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
                 use std::mem;
 
-                if let Some(root) = self.root {
+                if let Some(root) = ($root)(self) {
                     let mut stack = vec![(root, 0, false)];
 
                     while let Some((cur, i, is_left)) = stack.pop() {
@@ -123,3 +244,98 @@ macro_rules! impl_debug {
         }
     };
 }
+
+/// Generates `Entry` and `OccupiedEntry` for a map whose nodes have `key` and
+/// `val` fields. The module provides its own `VacantEntry` with `key` field
+/// and an `insert(self, V) -> &'a mut V` method. Extra type parameters of
+/// the map, given as `R: Bound = Default`, are passed on to `VacantEntry`.
+macro_rules! impl_entry {
+    ($($R: ident: $B: path = $D: ty),*) => {
+        pub enum Entry<'a, K, V $(, $R: $B = $D)*> {
+            Occupied(OccupiedEntry<'a, K, V>),
+            Vacant(VacantEntry<'a, K, V $(, $R)*>),
+        }
+
+        pub struct OccupiedEntry<'a, K, V> {
+            node: std::ptr::NonNull<Node<K, V>>,
+            _marker: std::marker::PhantomData<&'a mut (K, V)>,
+        }
+
+        impl<'a, K: Ord, V $(, $R: $B)*> Entry<'a, K, V $(, $R)*> {
+            pub fn key(&self) -> &K {
+                match self {
+                    Entry::Occupied(e) => e.key(),
+                    Entry::Vacant(e) => &e.key,
+                }
+            }
+
+            pub fn or_insert(self, default: V) -> &'a mut V {
+                self.or_insert_with(|| default)
+            }
+
+            pub fn or_insert_with<F: FnOnce() -> V>(self, f: F) -> &'a mut V {
+                match self {
+                    Entry::Occupied(e) => e.into_mut(),
+                    Entry::Vacant(e) => e.insert(f()),
+                }
+            }
+
+            pub fn or_insert_with_key<F: FnOnce(&K) -> V>(self, f: F) -> &'a mut V {
+                match self {
+                    Entry::Occupied(e) => e.into_mut(),
+                    Entry::Vacant(e) => {
+                        let val = f(&e.key);
+                        e.insert(val)
+                    }
+                }
+            }
+
+            pub fn or_default(self) -> &'a mut V
+            where
+                V: Default,
+            {
+                self.or_insert_with(V::default)
+            }
+
+            pub fn and_modify<F: FnOnce(&mut V)>(self, f: F) -> Self {
+                match self {
+                    Entry::Occupied(mut e) => {
+                        f(e.get_mut());
+                        Entry::Occupied(e)
+                    }
+                    e => e,
+                }
+            }
+        }
+
+        impl<'a, K, V> OccupiedEntry<'a, K, V> {
+            fn new(node: std::ptr::NonNull<Node<K, V>>) -> Self {
+                Self {
+                    node,
+                    _marker: std::marker::PhantomData,
+                }
+            }
+
+            pub fn key(&self) -> &K {
+                unsafe { &self.node.as_ref().key }
+            }
+
+            pub fn get(&self) -> &V {
+                unsafe { &self.node.as_ref().val }
+            }
+
+            pub fn get_mut(&mut self) -> &mut V {
+                unsafe { &mut self.node.as_mut().val }
+            }
+
+            pub fn into_mut(self) -> &'a mut V {
+                unsafe { &mut (*self.node.as_ptr()).val }
+            }
+
+            /// Replaces the value and returns the old one.
+            pub fn insert(&mut self, val: V) -> V {
+                std::mem::replace(self.get_mut(), val)
+            }
+        }
+    };
+}
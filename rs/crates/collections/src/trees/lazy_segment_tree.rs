@@ -0,0 +1,352 @@
+use super::monoid::{Affine, Bounded, Min, Monoid, Num, Sum};
+use super::segment_tree::bounds;
+use std::{marker::PhantomData, mem, ops::RangeBounds};
+
+/// Maps acting on the values of `M`, closed under composition.
+pub trait Action<M: Monoid> {
+    type F: Clone;
+
+    fn identity() -> Self::F;
+    /// `f` after `g`.
+    fn compose(f: &Self::F, g: &Self::F) -> Self::F;
+    /// Applies `f` to `x`, the aggregate of `len` elements.
+    fn apply(f: &Self::F, x: &M::T, len: usize) -> M::T;
+}
+
+/// No updates, for trees that only take point writes.
+impl<M: Monoid> Action<M> for () {
+    type F = ();
+
+    fn identity() {}
+    fn compose(_: &(), _: &()) {}
+
+    fn apply(_: &(), x: &M::T, _: usize) -> M::T {
+        x.clone()
+    }
+}
+
+/// Adds a constant to every element. Under a minimum it acts on
+/// `Min<Option<T>>`, whose empty `None` is left alone, since the `T::MAX`
+/// identity of `Min<T>` can't be told from an element holding it.
+pub struct Add<T>(PhantomData<T>);
+
+impl<T: Num> Action<Sum<T>> for Add<T> {
+    type F = T;
+
+    fn identity() -> T {
+        T::default()
+    }
+
+    fn compose(f: &T, g: &T) -> T {
+        *f + *g
+    }
+
+    fn apply(f: &T, x: &T, len: usize) -> T {
+        *x + *f * T::from_usize(len)
+    }
+}
+
+impl<T: Num + Ord> Action<Min<Option<T>>> for Add<T> {
+    type F = T;
+
+    fn identity() -> T {
+        T::default()
+    }
+
+    fn compose(f: &T, g: &T) -> T {
+        *f + *g
+    }
+
+    fn apply(f: &T, x: &Option<T>, _: usize) -> Option<T> {
+        x.map(|x| x + *f)
+    }
+}
+
+/// Overwrites every element, `None` keeps them.
+pub struct Assign<T>(PhantomData<T>);
+
+impl<T: Num> Action<Sum<T>> for Assign<T> {
+    type F = Option<T>;
+
+    fn identity() -> Option<T> {
+        None
+    }
+
+    fn compose(f: &Option<T>, g: &Option<T>) -> Option<T> {
+        f.or(*g)
+    }
+
+    fn apply(f: &Option<T>, x: &T, len: usize) -> T {
+        f.map_or(*x, |v| v * T::from_usize(len))
+    }
+}
+
+impl<T: Copy + Ord + Bounded> Action<Min<T>> for Assign<T> {
+    type F = Option<T>;
+
+    fn identity() -> Option<T> {
+        None
+    }
+
+    fn compose(f: &Option<T>, g: &Option<T>) -> Option<T> {
+        f.or(*g)
+    }
+
+    fn apply(f: &Option<T>, x: &T, _: usize) -> T {
+        f.unwrap_or(*x)
+    }
+}
+
+impl<T: Num> Action<Sum<T>> for Affine<T> {
+    type F = (T, T);
+
+    fn identity() -> (T, T) {
+        (T::from_usize(1), T::default())
+    }
+
+    fn compose(&(a, b): &(T, T), &(c, d): &(T, T)) -> (T, T) {
+        (a * c, a * d + b)
+    }
+
+    fn apply(&(a, b): &(T, T), x: &T, len: usize) -> T {
+        a * *x + b * T::from_usize(len)
+    }
+}
+
+/// Segment tree with lazy propagation: range updates by the maps of `A`
+/// and range aggregates under `M`, both in O(log n).
+///
+/// Pending maps are kept on inner nodes and pushed down before any path
+/// through them is read or rewritten, so queries take `&mut self` too.
+pub struct LazySegTree<M: Monoid, A: Action<M>> {
+    data: Vec<M::T>,
+    lazy: Vec<A::F>,
+    size: usize,
+    offset: usize,
+    log: u32,
+}
+
+impl<M: Monoid, A: Action<M>> LazySegTree<M, A> {
+    /// `size` elements, all `M::identity()`.
+    pub fn new(size: usize) -> Self {
+        Self::from((0..size).map(|_| M::identity()).collect())
+    }
+
+    pub fn from(v: Vec<M::T>) -> Self {
+        let size = v.len();
+        let offset = size.next_power_of_two();
+        let mut data = vec![M::identity(); offset * 2];
+        for (i, x) in v.into_iter().enumerate() {
+            data[offset + i] = x;
+        }
+        let mut st = Self {
+            data,
+            lazy: vec![A::identity(); offset],
+            size,
+            offset,
+            log: offset.trailing_zeros(),
+        };
+        for k in (1..offset).rev() {
+            st.update(k);
+        }
+        st
+    }
+
+    pub fn len(&self) -> usize {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    pub fn get(&mut self, i: usize) -> M::T {
+        assert!(i < self.size, "index out of range");
+        let k = i + self.offset;
+        for h in (1..=self.log).rev() {
+            self.push(k >> h);
+        }
+        self.data[k].clone()
+    }
+
+    pub fn set(&mut self, i: usize, val: M::T) {
+        assert!(i < self.size, "index out of range");
+        let k = i + self.offset;
+        for h in (1..=self.log).rev() {
+            self.push(k >> h);
+        }
+        self.data[k] = val;
+        for h in 1..=self.log {
+            self.update(k >> h);
+        }
+    }
+
+    /// Aggregate over `range`, `M::identity()` when it is empty.
+    pub fn query<R: RangeBounds<usize>>(&mut self, range: R) -> M::T {
+        let (l, r) = bounds(range, self.size);
+        if l == r {
+            return M::identity();
+        }
+        let (mut l, mut r) = (l + self.offset, r + self.offset);
+        self.push_bounds(l, r);
+
+        let (mut sl, mut sr) = (M::identity(), M::identity());
+        while l < r {
+            if l & 1 == 1 {
+                sl = M::op(&sl, &self.data[l]);
+                l += 1;
+            }
+            if r & 1 == 1 {
+                r -= 1;
+                sr = M::op(&self.data[r], &sr);
+            }
+            l >>= 1;
+            r >>= 1;
+        }
+        M::op(&sl, &sr)
+    }
+
+    /// Applies `f` to every element in `range`.
+    pub fn apply<R: RangeBounds<usize>>(&mut self, range: R, f: A::F) {
+        let (l, r) = bounds(range, self.size);
+        if l == r {
+            return;
+        }
+        let (l, r) = (l + self.offset, r + self.offset);
+        self.push_bounds(l, r);
+
+        let (mut a, mut b) = (l, r);
+        while a < b {
+            if a & 1 == 1 {
+                self.apply_node(a, &f);
+                a += 1;
+            }
+            if b & 1 == 1 {
+                b -= 1;
+                self.apply_node(b, &f);
+            }
+            a >>= 1;
+            b >>= 1;
+        }
+
+        for h in 1..=self.log {
+            if (l >> h) << h != l {
+                self.update(l >> h);
+            }
+            if (r >> h) << h != r {
+                self.update((r - 1) >> h);
+            }
+        }
+    }
+
+    /// Returns the largest `r` such that `pred` holds on the aggregate of
+    /// `l..r`, assuming `pred` is monotone and holds on the identity.
+    pub fn max_right<F: FnMut(&M::T) -> bool>(&mut self, l: usize, mut pred: F) -> usize {
+        assert!(l <= self.size, "index out of range");
+        debug_assert!(pred(&M::identity()));
+        if l == self.size {
+            return self.size;
+        }
+
+        let mut k = l + self.offset;
+        for h in (1..=self.log).rev() {
+            self.push(k >> h);
+        }
+        let mut acc = M::identity();
+        loop {
+            k >>= k.trailing_zeros();
+            let next = M::op(&acc, &self.data[k]);
+            if !pred(&next) {
+                while k < self.offset {
+                    self.push(k);
+                    k *= 2;
+                    let next = M::op(&acc, &self.data[k]);
+                    if pred(&next) {
+                        acc = next;
+                        k += 1;
+                    }
+                }
+                return k - self.offset;
+            }
+            acc = next;
+            k += 1;
+            if k.is_power_of_two() {
+                return self.size;
+            }
+        }
+    }
+
+    /// Returns the smallest `l` such that `pred` holds on the aggregate of
+    /// `l..r`, assuming `pred` is monotone and holds on the identity.
+    pub fn min_left<F: FnMut(&M::T) -> bool>(&mut self, r: usize, mut pred: F) -> usize {
+        assert!(r <= self.size, "index out of range");
+        debug_assert!(pred(&M::identity()));
+        if r == 0 {
+            return 0;
+        }
+
+        let mut k = r + self.offset;
+        for h in (1..=self.log).rev() {
+            self.push((k - 1) >> h);
+        }
+        let mut acc = M::identity();
+        loop {
+            k -= 1;
+            while k > 1 && k % 2 == 1 {
+                k >>= 1;
+            }
+            let next = M::op(&self.data[k], &acc);
+            if !pred(&next) {
+                while k < self.offset {
+                    self.push(k);
+                    k = k * 2 + 1;
+                    let next = M::op(&self.data[k], &acc);
+                    if pred(&next) {
+                        acc = next;
+                        k -= 1;
+                    }
+                }
+                return k + 1 - self.offset;
+            }
+            acc = next;
+            if k.is_power_of_two() {
+                return 0;
+            }
+        }
+    }
+
+    /// Number of elements under node `k`.
+    fn width(&self, k: usize) -> usize {
+        self.offset >> k.ilog2()
+    }
+
+    fn update(&mut self, k: usize) {
+        self.data[k] = M::op(&self.data[k * 2], &self.data[k * 2 + 1]);
+    }
+
+    fn apply_node(&mut self, k: usize, f: &A::F) {
+        self.data[k] = A::apply(f, &self.data[k], self.width(k));
+        if k < self.offset {
+            self.lazy[k] = A::compose(f, &self.lazy[k]);
+        }
+    }
+
+    fn push(&mut self, k: usize) {
+        let f = mem::replace(&mut self.lazy[k], A::identity());
+        self.apply_node(k * 2, &f);
+        self.apply_node(k * 2 + 1, &f);
+    }
+
+    /// Pushes down every map above the leaves `l` and `r - 1`, except on
+    /// nodes lying wholly inside `[l, r)`.
+    fn push_bounds(&mut self, l: usize, r: usize) {
+        for h in (1..=self.log).rev() {
+            if (l >> h) << h != l {
+                self.push(l >> h);
+            }
+            if (r >> h) << h != r {
+                self.push((r - 1) >> h);
+            }
+        }
+    }
+}
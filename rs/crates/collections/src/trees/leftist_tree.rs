@@ -1,18 +1,19 @@
+use crate::invariant::InvariantError;
 use std::{mem, ptr, rc::Rc};
 
 #[derive(Debug)]
-struct Node<T: Ord> {
+pub(crate) struct Node<T: Ord> {
     elem: T,
     left: Link<T>,
     right: Link<T>,
-    dist: usize,
+    pub(crate) dist: usize,
 }
 
 type Link<T> = Option<Box<Node<T>>>;
 
 #[derive(Debug)]
 pub struct LeftistTree<T: Ord> {
-    root: Link<T>,
+    pub(crate) root: Link<T>,
     size: usize,
 }
 
@@ -22,7 +23,7 @@ impl<T: Ord> Node<T> {
             elem,
             left: None,
             right: None,
-            dist: 0,
+            dist: 1,
         }
     }
 }
@@ -48,6 +49,7 @@ impl<T: Ord> LeftistTree<T> {
     pub fn merge(&mut self, other: Self) {
         self.size += other.size;
         self.root = Self::merge_node(self.root.take(), other.root);
+        debug_invariants!(self);
     }
 
     pub fn len(&self) -> usize {
@@ -86,14 +88,42 @@ impl<T: Ord> LeftistTree<T> {
         self.size += 1;
         let node = Some(Box::new(Node::new(val)));
         self.root = Self::merge_node(self.root.take(), node);
+        debug_invariants!(self);
     }
 
     pub fn pop(&mut self) -> Option<T> {
-        self.root.take().map(|x| {
+        let top = self.root.take().map(|x| {
             self.root = Self::merge_node(x.left, x.right);
             self.size -= 1;
             x.elem
-        })
+        });
+        debug_invariants!(self);
+        top
+    }
+
+    /// Checks the max-heap order, the `dist` of every node (the distance
+    /// to the nearest empty link) with the leftist property, and the size.
+    pub fn validate(&self) -> Result<(), InvariantError> {
+        let mut stack: Vec<&Node<T>> = self.root.as_deref().into_iter().collect();
+        let mut cnt = 0;
+
+        while let Some(p) = stack.pop() {
+            cnt += 1;
+            let (ld, rd) = (Self::get_node_dist(&p.left), Self::get_node_dist(&p.right));
+            if ld < rd || p.dist != rd + 1 {
+                return Err(InvariantError::Dist);
+            }
+            for c in [&p.left, &p.right].into_iter().flatten() {
+                if c.elem > p.elem {
+                    return Err(InvariantError::HeapOrder);
+                }
+                stack.push(c);
+            }
+        }
+        if cnt != self.size {
+            return Err(InvariantError::Size { expected: cnt, found: self.size });
+        }
+        Ok(())
     }
 }
 
@@ -119,7 +149,7 @@ impl<T> PstNode<T> {
             elem,
             left: None,
             right: None,
-            dist: 0,
+            dist: 1,
         }
     }
 }
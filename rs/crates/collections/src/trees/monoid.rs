@@ -0,0 +1,148 @@
+use std::{
+    marker::PhantomData,
+    ops::{Add, BitXor, Mul, Rem},
+};
+
+/// An associative operation with an identity element.
+pub trait Monoid {
+    type T: Clone;
+
+    fn identity() -> Self::T;
+    fn op(a: &Self::T, b: &Self::T) -> Self::T;
+}
+
+/// Numbers the ready-made monoids and actions work over.
+pub trait Num: Copy + Default + Add<Output = Self> + Mul<Output = Self> {
+    fn from_usize(n: usize) -> Self;
+}
+
+/// Types with extreme values, the identities of [`Min`] and [`Max`].
+pub trait Bounded {
+    const MIN: Self;
+    const MAX: Self;
+}
+
+macro_rules! impl_num {
+    ($($t: ty)*) => {$(
+        impl Num for $t {
+            fn from_usize(n: usize) -> Self {
+                n as $t
+            }
+        }
+
+        impl Bounded for $t {
+            const MIN: Self = <$t>::MIN;
+            const MAX: Self = <$t>::MAX;
+        }
+    )*};
+}
+
+impl_num!(i8 i16 i32 i64 i128 isize u8 u16 u32 u64 u128 usize);
+
+/// Addition, with `T::default()` as zero.
+pub struct Sum<T>(PhantomData<T>);
+
+impl<T: Num> Monoid for Sum<T> {
+    type T = T;
+
+    fn identity() -> T {
+        T::default()
+    }
+
+    fn op(a: &T, b: &T) -> T {
+        *a + *b
+    }
+}
+
+pub struct Min<T>(PhantomData<T>);
+
+impl<T: Copy + Ord + Bounded> Monoid for Min<T> {
+    type T = T;
+
+    fn identity() -> T {
+        T::MAX
+    }
+
+    fn op(a: &T, b: &T) -> T {
+        *a.min(b)
+    }
+}
+
+/// `None` is the empty aggregate, so no value has to stand in for it and
+/// actions can tell empty ranges from ones holding `T::MAX`.
+impl<T: Copy + Ord> Monoid for Min<Option<T>> {
+    type T = Option<T>;
+
+    fn identity() -> Option<T> {
+        None
+    }
+
+    fn op(a: &Option<T>, b: &Option<T>) -> Option<T> {
+        match (a, b) {
+            (Some(a), Some(b)) => Some(*a.min(b)),
+            _ => a.or(*b),
+        }
+    }
+}
+
+pub struct Max<T>(PhantomData<T>);
+
+impl<T: Copy + Ord + Bounded> Monoid for Max<T> {
+    type T = T;
+
+    fn identity() -> T {
+        T::MIN
+    }
+
+    fn op(a: &T, b: &T) -> T {
+        *a.max(b)
+    }
+}
+
+/// Greatest common divisor of non-negative numbers, with zero as identity.
+pub struct Gcd<T>(PhantomData<T>);
+
+impl<T: Num + Rem<Output = T> + PartialEq> Monoid for Gcd<T> {
+    type T = T;
+
+    fn identity() -> T {
+        T::default()
+    }
+
+    fn op(a: &T, b: &T) -> T {
+        let (mut a, mut b) = (*a, *b);
+        while b != T::default() {
+            (a, b) = (b, a % b);
+        }
+        a
+    }
+}
+
+pub struct Xor<T>(PhantomData<T>);
+
+impl<T: Copy + Default + BitXor<Output = T>> Monoid for Xor<T> {
+    type T = T;
+
+    fn identity() -> T {
+        T::default()
+    }
+
+    fn op(a: &T, b: &T) -> T {
+        *a ^ *b
+    }
+}
+
+/// Maps `x -> a * x + b` as pairs `(a, b)`, `op(f, g)` applies `f` first.
+pub struct Affine<T>(PhantomData<T>);
+
+impl<T: Num> Monoid for Affine<T> {
+    type T = (T, T);
+
+    fn identity() -> (T, T) {
+        (T::from_usize(1), T::default())
+    }
+
+    fn op(&(a, b): &(T, T), &(c, d): &(T, T)) -> (T, T) {
+        (c * a, c * b + d)
+    }
+}
@@ -1,13 +1,21 @@
 #![allow(dead_code)]
 
 use super::binary_tree;
+use crate::invariant::InvariantError;
 use ptr::NonNull;
-use std::{cmp::Ordering, fmt, marker::PhantomData, mem, ops::Add, ptr, todo};
+use std::{
+    cmp::Ordering,
+    fmt,
+    marker::PhantomData,
+    mem,
+    ops::{Add, Bound, RangeBounds},
+    ptr, todo,
+};
 
 type Link<K, V> = Option<NonNull<Node<K, V>>>;
 
 #[derive(Debug, PartialEq, Eq)]
-enum Color {
+pub(crate) enum Color {
     Red,
     Black,
 }
@@ -17,16 +25,24 @@ use Color::*;
 use super::treap::TreapMap;
 
 #[derive(Debug)]
-struct Node<K, V> {
+pub struct Node<K, V> {
     key: K,
     val: V,
     left: Link<K, V>,
     right: Link<K, V>,
-    color: Color,
-    size: usize,
+    pub(crate) color: Color,
+    pub(crate) size: usize,
 }
 
 impl_debug!(RBTreeMap);
+impl_entry!();
+
+pub struct VacantEntry<'a, K, V> {
+    key: K,
+    // search path from the root, with the side taken at each node
+    path: Vec<(NonNull<Node<K, V>>, bool)>,
+    map: &'a mut RBTreeMap<K, V>,
+}
 
 pub struct RBTreeMap<K, V> {
     root: Link<K, V>,
@@ -91,8 +107,11 @@ impl<K, V> Node<K, V> {
     }
 
     unsafe fn refresh_size(&mut self) { unsafe {
-        let f = |u: Link<K, V>| u.map_or(0, |x| x.as_ref().size);
-        self.size = 1 + f(self.left) + f(self.right);
+        self.size = 1 + Self::size_of(self.left) + Self::size_of(self.right);
+    }}
+
+    unsafe fn size_of(node: Link<K, V>) -> usize { unsafe {
+        node.map_or(0, |x| x.as_ref().size)
     }}
 
     fn take_left(&mut self) -> Link<K, V> {
@@ -105,6 +124,20 @@ impl<K, V> Node<K, V> {
         self.right.take()
     }
 
+    unsafe fn free(node: Link<K, V>) { unsafe {
+        let mut s = vec![];
+        if let Some(x) = node { s.push(x) }
+
+        while let Some(x) = s.pop() {
+            let node = Box::from_raw(x.as_ptr());
+            if let Some(u) = node.left { s.push(u) }
+            if let Some(u) = node.right {
+                debug_assert!(!u.as_ref().is_red());
+                s.push(u);
+            }
+        }
+    }}
+
     fn is_red(&self) -> bool {
         match self.color {
             Red => true,
@@ -197,30 +230,50 @@ impl<K: Ord, V> RBTreeMap<K, V> {
         self.root.map_or(0, |x| unsafe { x.as_ref().size })
     }
 
+    pub fn iter(&self) -> binary_tree::Range<'_, Node<K, V>> {
+        self.range(..)
+    }
+
+    /// Iterates over the entries whose keys fall into `range`, from either end.
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> binary_tree::Range<'_, Node<K, V>> {
+        binary_tree::Range::new(self.root, range)
+    }
+
     pub fn insert(&mut self, key: K, val: V) {
-        unsafe {
-            self.root.replace(Self::insert_node(self.root, key, val));
-            if let Some(mut x) = self.root { x.as_mut().set_black() }
+        match self.entry(key) {
+            Entry::Occupied(mut e) => {
+                e.insert(val);
+            }
+            Entry::Vacant(e) => {
+                e.insert(val);
+            }
         }
     }
 
-    unsafe fn insert_node(node: Link<K, V>, key: K, val: V) -> NonNull<Node<K, V>> { unsafe {
-        if let Some(mut nd) = node {
-            let p = nd.as_mut();
-            match p.key.cmp(&key) {
-                Ordering::Equal => p.val = val,
-                Ordering::Greater => {
-                    p.replace_left(Self::insert_node(p.left, key, val));
-                }
-                Ordering::Less => {
-                    p.replace_right(Self::insert_node(p.right, key, val));
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V> {
+        let mut path = vec![];
+        let mut cur = self.root;
+        while let Some(p) = cur {
+            unsafe {
+                match p.as_ref().key.cmp(&key) {
+                    Ordering::Equal => return Entry::Occupied(OccupiedEntry::new(p)),
+                    Ordering::Greater => {
+                        path.push((p, true));
+                        cur = p.as_ref().left;
+                    }
+                    Ordering::Less => {
+                        path.push((p, false));
+                        cur = p.as_ref().right;
+                    }
                 }
             }
-            Self::fixup(nd)
-        } else {
-            Node::new(key, val).wrap()
         }
-    }}
+        Entry::Vacant(VacantEntry {
+            key,
+            path,
+            map: self,
+        })
+    }
 
     unsafe fn fixup(mut node: NonNull<Node<K, V>>) -> NonNull<Node<K, V>> { unsafe {
         if !Self::is_some_red(node.as_ref().left) && Self::is_some_red(node.as_ref().right) {
@@ -254,6 +307,106 @@ impl<K: Ord, V> RBTreeMap<K, V> {
         None
     }
 
+    /// Returns the `k`-th smallest entry (0-indexed).
+    pub fn select(&self, mut k: usize) -> Option<(&K, &V)> {
+        let mut cur = self.root;
+        while let Some(p) = cur {
+            unsafe {
+                let l = Node::size_of(p.as_ref().left);
+                match k.cmp(&l) {
+                    Ordering::Equal => return Some((&(*p.as_ptr()).key, &(*p.as_ptr()).val)),
+                    Ordering::Less => cur = p.as_ref().left,
+                    Ordering::Greater => {
+                        k -= l + 1;
+                        cur = p.as_ref().right;
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Returns the number of keys strictly less than `key`.
+    pub fn rank(&self, key: &K) -> usize {
+        self.count_less(key, false)
+    }
+
+    /// Returns the number of keys falling into `range`.
+    pub fn count_range<R: RangeBounds<K>>(&self, range: R) -> usize {
+        let lo = match range.start_bound() {
+            Bound::Included(k) => self.count_less(k, false),
+            Bound::Excluded(k) => self.count_less(k, true),
+            Bound::Unbounded => 0,
+        };
+        let hi = match range.end_bound() {
+            Bound::Included(k) => self.count_less(k, true),
+            Bound::Excluded(k) => self.count_less(k, false),
+            Bound::Unbounded => self.len(),
+        };
+        hi.saturating_sub(lo)
+    }
+
+    /// Counts keys `< key`, or `<= key` if `inclusive`.
+    fn count_less(&self, key: &K, inclusive: bool) -> usize {
+        let mut cur = self.root;
+        let mut cnt = 0;
+        while let Some(p) = cur {
+            unsafe {
+                let p = p.as_ref();
+                match p.key.cmp(key) {
+                    Ordering::Less => {
+                        cnt += Node::size_of(p.left) + 1;
+                        cur = p.right;
+                    }
+                    Ordering::Equal if inclusive => return cnt + Node::size_of(p.left) + 1,
+                    Ordering::Equal => return cnt + Node::size_of(p.left),
+                    Ordering::Greater => cur = p.left,
+                }
+            }
+        }
+        cnt
+    }
+
+    /// Checks key order, subtree sizes and the left-leaning red-black rules.
+    pub fn validate(&self) -> Result<(), InvariantError> {
+        if Self::is_some_red(self.root) {
+            return Err(InvariantError::RedRoot);
+        }
+        unsafe { Self::validate_node(self.root, None, None).map(|_| ()) }
+    }
+
+    /// Returns the black height of a valid subtree with keys in `(lo, hi)`.
+    unsafe fn validate_node<'a>(
+        node: Link<K, V>,
+        lo: Option<&'a K>,
+        hi: Option<&'a K>,
+    ) -> Result<usize, InvariantError> { unsafe {
+        let Some(p) = node else { return Ok(0) };
+        let key: &'a K = &(*p.as_ptr()).key;
+        let p = p.as_ref();
+
+        if lo.is_some_and(|k| k >= key) || hi.is_some_and(|k| k <= key) {
+            return Err(InvariantError::Order);
+        }
+        if Self::is_some_red(p.right) {
+            return Err(InvariantError::RedRightLink);
+        }
+        if p.is_red() && Self::is_some_red(p.left) {
+            return Err(InvariantError::DoubleRed);
+        }
+        let expected = 1 + Node::size_of(p.left) + Node::size_of(p.right);
+        if p.size != expected {
+            return Err(InvariantError::Size { expected, found: p.size });
+        }
+
+        let l = Self::validate_node(p.left, lo, Some(key))?;
+        let r = Self::validate_node(p.right, Some(key), hi)?;
+        if l != r {
+            return Err(InvariantError::BlackHeight);
+        }
+        Ok(l + !p.is_red() as usize)
+    }}
+
     fn is_some_red(node: Link<K, V>) -> bool {
         node.is_some_and(|x| unsafe { x.as_ref().is_red() })
     }
@@ -272,6 +425,7 @@ impl<K: Ord, V> RBTreeMap<K, V> {
                     if let Some(mut x) = self.root { x.as_mut().set_black() }
                 }
             }
+            debug_invariants!(self);
             true
         }
     }
@@ -344,18 +498,361 @@ impl<K: Ord, V> RBTreeMap<K, V> {
     }}
 }
 
+/// A standalone subtree with a black root, together with its black height.
+type Tree<K, V> = (Link<K, V>, usize);
+/// A detached root between its former subtrees.
+type Exposed<K, V> = (Tree<K, V>, NonNull<Node<K, V>>, Tree<K, V>);
+
+impl<K: Ord, V> RBTreeMap<K, V> {
+    fn from_tree((root, _): Tree<K, V>) -> Self {
+        let t = Self {
+            root,
+            _marker: PhantomData,
+        };
+        debug_invariants!(t);
+        t
+    }
+
+    fn into_tree(mut self) -> Tree<K, V> {
+        let root = self.root.take();
+        (root, unsafe { Self::black_height(root) })
+    }
+
+    /// Builds a map from `left`, `(key, val)` and `right`, where all keys of
+    /// `left` are less than `key` and all keys of `right` are greater.
+    /// O(log n).
+    pub fn join(left: Self, key: K, val: V, right: Self) -> Self {
+        assert!(left.iter().next_back().is_none_or(|(k, _)| k < &key));
+        assert!(right.iter().next().is_none_or(|(k, _)| k > &key));
+        let x = Node::new(key, val).wrap();
+        unsafe { Self::from_tree(Self::join_tree(left.into_tree(), x, right.into_tree())) }
+    }
+
+    /// Splits the map in two at `key`, returning everything greater than or
+    /// equal to it. O(log n).
+    pub fn split_off(&mut self, key: &K) -> Self {
+        let (l, m, r) = unsafe {
+            let root = self.root.take();
+            Self::split_tree((root, Self::black_height(root)), key)
+        };
+        self.root = l.0;
+        debug_invariants!(self);
+        let r = match m {
+            Some(m) => unsafe { Self::join_tree((None, 0), m, r) },
+            None => r,
+        };
+        Self::from_tree(r)
+    }
+
+    /// Moves all entries of `other` into `self`, values of `other` win on
+    /// equal keys. O(log n) if the key ranges do not overlap, otherwise
+    /// the same as `union`.
+    pub fn append(&mut self, other: &mut Self) {
+        let a = mem::take(self);
+        let b = mem::take(other);
+
+        let a_max = a.iter().next_back().map(|(k, _)| k as *const K);
+        let b_min = b.iter().next().map(|(k, _)| k as *const K);
+        let disjoint = match (a_max, b_min) {
+            (Some(x), Some(y)) => unsafe { *x < *y },
+            _ => true,
+        };
+
+        *self = if disjoint {
+            unsafe { Self::from_tree(Self::join2(a.into_tree(), b.into_tree())) }
+        } else {
+            a.union(b)
+        };
+    }
+
+    /// Entries present in either map, values of `other` win on equal keys.
+    /// O(m log(n/m + 1)) for sizes m <= n.
+    pub fn union(self, other: Self) -> Self {
+        unsafe { Self::from_tree(Self::union_tree(self.into_tree(), other.into_tree())) }
+    }
+
+    /// Entries of `self` whose keys are also in `other`.
+    pub fn intersection(self, other: Self) -> Self {
+        unsafe { Self::from_tree(Self::intersection_tree(self.into_tree(), other.into_tree())) }
+    }
+
+    /// Entries of `self` whose keys are not in `other`.
+    pub fn difference(self, other: Self) -> Self {
+        unsafe { Self::from_tree(Self::difference_tree(self.into_tree(), other.into_tree())) }
+    }
+
+    unsafe fn black_height(node: Link<K, V>) -> usize { unsafe {
+        let mut h = 0;
+        let mut cur = node;
+        while let Some(p) = cur {
+            h += !p.as_ref().is_red() as usize;
+            cur = p.as_ref().left;
+        }
+        h
+    }}
+
+    /// Detaches the root from its children, which become standalone trees.
+    unsafe fn expose(mut node: NonNull<Node<K, V>>, bh: usize) -> Exposed<K, V> { unsafe {
+        let p = node.as_mut();
+        let child_bh = bh - !p.is_red() as usize;
+        let blacken = |c: Link<K, V>| -> Tree<K, V> {
+            match c {
+                Some(mut x) if x.as_ref().is_red() => {
+                    x.as_mut().set_black();
+                    (c, child_bh + 1)
+                }
+                _ => (c, child_bh),
+            }
+        };
+        let l = blacken(p.take_left());
+        let r = blacken(p.take_right());
+        p.set_red();
+        (l, node, r)
+    }}
+
+    unsafe fn attach(mut x: NonNull<Node<K, V>>, l: Link<K, V>, r: Link<K, V>) -> NonNull<Node<K, V>> { unsafe {
+        let p = x.as_mut();
+        p.left = l;
+        p.right = r;
+        p.set_red();
+        p.refresh_size();
+        x
+    }}
+
+    /// Joins `l < x < r` by hanging `x` on the spine of the taller tree at
+    /// the black height of the shorter one, then fixing up the spine.
+    unsafe fn join_tree(l: Tree<K, V>, x: NonNull<Node<K, V>>, r: Tree<K, V>) -> Tree<K, V> { unsafe {
+        let (lbh, rbh) = (l.1, r.1);
+        let mut root = match lbh.cmp(&rbh) {
+            Ordering::Greater => Self::join_right(l.0, lbh, x, r.0, rbh),
+            Ordering::Less => Self::join_left(l.0, lbh, x, r.0, rbh),
+            Ordering::Equal => Self::attach(x, l.0, r.0),
+        };
+        let bh = lbh.max(rbh) + root.as_ref().is_red() as usize;
+        root.as_mut().set_black();
+        (Some(root), bh)
+    }}
+
+    unsafe fn join_right(
+        t: Link<K, V>,
+        bh: usize,
+        x: NonNull<Node<K, V>>,
+        r: Link<K, V>,
+        rbh: usize,
+    ) -> NonNull<Node<K, V>> { unsafe {
+        match t {
+            Some(mut p) if p.as_ref().is_red() || bh > rbh => {
+                let child_bh = bh - !p.as_ref().is_red() as usize;
+                let c = Self::join_right(p.as_ref().right, child_bh, x, r, rbh);
+                p.as_mut().set_right(Some(c));
+                Self::fixup(p)
+            }
+            _ => Self::attach(x, t, r),
+        }
+    }}
+
+    unsafe fn join_left(
+        l: Link<K, V>,
+        lbh: usize,
+        x: NonNull<Node<K, V>>,
+        t: Link<K, V>,
+        bh: usize,
+    ) -> NonNull<Node<K, V>> { unsafe {
+        match t {
+            Some(mut p) if p.as_ref().is_red() || bh > lbh => {
+                let child_bh = bh - !p.as_ref().is_red() as usize;
+                let c = Self::join_left(l, lbh, x, p.as_ref().left, child_bh);
+                p.as_mut().set_left(Some(c));
+                Self::fixup(p)
+            }
+            _ => Self::attach(x, l, t),
+        }
+    }}
+
+    /// Joins `l < r` without a middle node.
+    unsafe fn join2(l: Tree<K, V>, r: Tree<K, V>) -> Tree<K, V> { unsafe {
+        match l.0 {
+            Some(root) => {
+                let (l, last) = Self::split_last(root, l.1);
+                Self::join_tree(l, last, r)
+            }
+            None => r,
+        }
+    }}
+
+    unsafe fn split_last(root: NonNull<Node<K, V>>, bh: usize) -> (Tree<K, V>, NonNull<Node<K, V>>) { unsafe {
+        let (l, p, r) = Self::expose(root, bh);
+        match r.0 {
+            Some(x) => {
+                let (r, last) = Self::split_last(x, r.1);
+                (Self::join_tree(l, p, r), last)
+            }
+            None => (l, p),
+        }
+    }}
+
+    /// Splits into keys `< key`, the node with `key` if any, and keys `> key`.
+    unsafe fn split_tree(t: Tree<K, V>, key: &K) -> (Tree<K, V>, Link<K, V>, Tree<K, V>) { unsafe {
+        let Some(root) = t.0 else {
+            return ((None, 0), None, (None, 0));
+        };
+        let (l, p, r) = Self::expose(root, t.1);
+        match key.cmp(&p.as_ref().key) {
+            Ordering::Less => {
+                let (ll, m, lr) = Self::split_tree(l, key);
+                (ll, m, Self::join_tree(lr, p, r))
+            }
+            Ordering::Greater => {
+                let (rl, m, rr) = Self::split_tree(r, key);
+                (Self::join_tree(l, p, rl), m, rr)
+            }
+            Ordering::Equal => (l, Some(p), r),
+        }
+    }}
+
+    unsafe fn union_tree(a: Tree<K, V>, b: Tree<K, V>) -> Tree<K, V> { unsafe {
+        let (Some(root), Some(_)) = (a.0, b.0) else {
+            return if a.0.is_some() { a } else { b };
+        };
+        let (l1, p, r1) = Self::expose(root, a.1);
+        let (l2, m, r2) = Self::split_tree(b, &p.as_ref().key);
+        let l = Self::union_tree(l1, l2);
+        let r = Self::union_tree(r1, r2);
+        match m {
+            Some(m) => {
+                Node::free(Some(p));
+                Self::join_tree(l, m, r)
+            }
+            None => Self::join_tree(l, p, r),
+        }
+    }}
+
+    unsafe fn intersection_tree(a: Tree<K, V>, b: Tree<K, V>) -> Tree<K, V> { unsafe {
+        let (Some(root), Some(_)) = (a.0, b.0) else {
+            Node::free(a.0);
+            Node::free(b.0);
+            return (None, 0);
+        };
+        let (l1, p, r1) = Self::expose(root, a.1);
+        let (l2, m, r2) = Self::split_tree(b, &p.as_ref().key);
+        let l = Self::intersection_tree(l1, l2);
+        let r = Self::intersection_tree(r1, r2);
+        if m.is_some() {
+            Node::free(m);
+            Self::join_tree(l, p, r)
+        } else {
+            Node::free(Some(p));
+            Self::join2(l, r)
+        }
+    }}
+
+    unsafe fn difference_tree(a: Tree<K, V>, b: Tree<K, V>) -> Tree<K, V> { unsafe {
+        let (Some(_), Some(root)) = (a.0, b.0) else {
+            Node::free(b.0);
+            return a;
+        };
+        let (l2, q, r2) = Self::expose(root, b.1);
+        let (l1, m, r1) = Self::split_tree(a, &q.as_ref().key);
+        Node::free(Some(q));
+        Node::free(m);
+        let l = Self::difference_tree(l1, l2);
+        let r = Self::difference_tree(r1, r2);
+        Self::join2(l, r)
+    }}
+}
+
+impl<'a, K: Ord, V> VacantEntry<'a, K, V> {
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    pub fn into_key(self) -> K {
+        self.key
+    }
+
+    pub fn insert(self, val: V) -> &'a mut V {
+        let node = Node::new(self.key, val).wrap();
+        unsafe {
+            // same as the recursive LLRB insertion: fix up every node on the
+            // way back to the root
+            let mut child = node;
+            for (mut p, is_left) in self.path.into_iter().rev() {
+                if is_left {
+                    p.as_mut().set_left(Some(child));
+                } else {
+                    p.as_mut().set_right(Some(child));
+                }
+                child = RBTreeMap::fixup(p);
+            }
+            child.as_mut().set_black();
+            self.map.root = Some(child);
+            debug_invariants!(self.map);
+            &mut (*node.as_ptr()).val
+        }
+    }
+}
+
 impl<K, V> Drop for RBTreeMap<K, V> {
     fn drop(&mut self) {
-        let mut s = vec![];
-        if let Some(x) = self.root { s.push(x) }
+        unsafe { Node::free(self.root.take()) }
+    }
+}
 
-        while let Some(x) = s.pop() {
-            let node = unsafe { Box::from_raw(x.as_ptr()) };
-            if let Some(u) = node.left { s.push(u) }
-            node.right.map(|u| unsafe {
-                debug_assert!(!u.as_ref().is_red());
-                s.push(u);
-            });
-        }
+impl<K, V> binary_tree::BinaryTreeNode for Node<K, V> {
+    type Key = K;
+    type Value = V;
+
+    fn left(&self) -> &Option<NonNull<Self>> {
+        &self.left
+    }
+
+    fn mut_left(&mut self) -> &mut Option<NonNull<Self>> {
+        &mut self.left
+    }
+
+    fn right(&self) -> &Option<NonNull<Self>> {
+        &self.right
+    }
+
+    fn mut_right(&mut self) -> &mut Option<NonNull<Self>> {
+        &mut self.right
+    }
+
+    fn move_kv(self) -> (Self::Key, Self::Value) {
+        (self.key, self.val)
+    }
+
+    fn kv(&self) -> (&Self::Key, &Self::Value) {
+        (&self.key, &self.val)
+    }
+}
+
+impl<K, V> binary_tree::BinaryTree for RBTreeMap<K, V> {
+    type Node = Node<K, V>;
+
+    fn root(&self) -> Option<NonNull<Self::Node>> {
+        self.root
+    }
+
+    fn mut_root(&mut self) -> &mut Option<NonNull<Self::Node>> {
+        &mut self.root
+    }
+}
+
+impl<K, V> IntoIterator for RBTreeMap<K, V> {
+    type Item = (K, V);
+    type IntoIter = binary_tree::IntoIter<Node<K, V>>;
+
+    fn into_iter(mut self) -> Self::IntoIter {
+        binary_tree::BinaryTree::into_iter(&mut self)
+    }
+}
+
+impl<'a, K: Ord, V> IntoIterator for &'a RBTreeMap<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = binary_tree::Range<'a, Node<K, V>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
     }
 }
@@ -1,111 +1,192 @@
 #![allow(dead_code)]
 
-use std::{cell::RefCell, mem, ptr::NonNull, rc::Rc, vec};
+use super::monoid::Monoid;
+use std::{
+    cell::RefCell,
+    mem,
+    ops::{Bound, RangeBounds},
+    ptr::NonNull,
+    rc::Rc,
+    vec,
+};
 
 use mem::size_of;
 
-pub struct SegTree<T, F>
-where
-    F: Fn(&T, &T) -> T,
-{
-    buf: Vec<Option<T>>,
+/// Segment tree over a monoid: point updates and range aggregates in
+/// O(log n). The monoid is a zero-sized type, so `op` inlines.
+pub struct SegTree<M: Monoid> {
+    buf: Vec<M::T>,
     size: usize,
     offset: usize,
-    _op: F,
 }
 
-impl<T: Clone, F> SegTree<T, F>
-where
-    F: Fn(&T, &T) -> T,
-{
-    pub fn new(size: usize, op: F) -> Self {
-        Self::cons(vec![], size, op)
+impl<M: Monoid> SegTree<M> {
+    /// `size` elements, all `M::identity()`.
+    pub fn new(size: usize) -> Self {
+        Self::from(vec![M::identity(); size])
     }
 
-    pub fn from(v: Vec<T>, op: F) -> Self {
-        let n = v.len();
-        Self::cons(v, n, op)
-    }
-
-    fn cons(data: Vec<T>, size: usize, op: F) -> Self {
-        let bit_num = mem::size_of::<usize>() * 8;
-        let offset = 1 << (bit_num - size.leading_zeros() as usize);
-        debug_assert!(offset >= size);
+    pub fn from(data: Vec<M::T>) -> Self {
+        let size = data.len();
+        let offset = size.next_power_of_two();
 
         let mut buf = Vec::with_capacity(offset * 2);
-        for _ in 0..offset * 2 {
-            buf.push(None);
-        }
-
-        let mut st = SegTree {
-            buf,
-            size,
-            offset,
-            _op: op,
-        };
+        buf.resize(offset, M::identity());
+        buf.extend(data);
+        buf.resize(offset * 2, M::identity());
 
-        if !data.is_empty() {
-            for (i, x) in data.into_iter().enumerate() {
-                st.buf[offset + i] = Some(x);
-            }
-            for i in (1..offset).rev() {
-                st.buf[i] = st.combine(&st.buf[i * 2], &st.buf[i * 2 + 1]);
-            }
+        let mut st = SegTree { buf, size, offset };
+        for i in (1..offset).rev() {
+            st.update(i);
         }
-
         st
     }
 
+    /// Element `i` is `f(i)`.
+    pub fn from_fn<F: FnMut(usize) -> M::T>(size: usize, f: F) -> Self {
+        Self::from((0..size).map(f).collect())
+    }
+
     pub fn len(&self) -> usize {
         self.size
     }
 
-    fn combine(&self, a: &Option<T>, b: &Option<T>) -> Option<T> {
-        if let (Some(x), Some(y)) = (a, b) {
-            let op = &self._op;
-            Some(op(x, y))
-        } else if a.is_some() || b.is_some() {
-            a.clone().or(b.clone())
-        } else {
-            None
-        }
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    fn update(&mut self, i: usize) {
+        self.buf[i] = M::op(&self.buf[i * 2], &self.buf[i * 2 + 1]);
+    }
+
+    pub fn get(&self, idx: usize) -> &M::T {
+        assert!(idx < self.size, "index out of range");
+        &self.buf[idx + self.offset]
     }
 
-    pub fn insert(&mut self, idx: usize, val: T) {
+    pub fn set(&mut self, idx: usize, val: M::T) {
+        assert!(idx < self.size, "index out of range");
         let mut i = idx + self.offset;
-        self.buf[i] = Some(val);
+        self.buf[i] = val;
 
         while i > 1 {
             i >>= 1;
-            self.buf[i] = self.combine(&self.buf[i * 2], &self.buf[i * 2 + 1]);
+            self.update(i);
         }
     }
 
-    /// **left** inclusive, **right** exclusive
-    pub fn query(&self, left: usize, right: usize) -> Option<T> {
-        if left >= right || right > self.size {
-            return None;
-        }
+    /// Aggregate over `range`, `M::identity()` when it is empty.
+    pub fn query<R: RangeBounds<usize>>(&self, range: R) -> M::T {
+        let (left, right) = bounds(range, self.size);
+        let (mut l, mut r) = (left + self.offset, right + self.offset);
+        let (mut sl, mut sr) = (M::identity(), M::identity());
 
-        let (mut l, mut r) = (left + self.offset, right + self.offset - 1);
-        let mut ans = self.buf[l].clone();
-        l += 1;
-
-        while l <= r {
+        while l < r {
             if l % 2 == 1 {
-                ans = self.combine(&ans, &self.buf[l]);
+                sl = M::op(&sl, &self.buf[l]);
                 l += 1;
             }
-            if r % 2 == 0 {
-                ans = self.combine(&ans, &self.buf[r]);
+            if r % 2 == 1 {
                 r -= 1;
+                sr = M::op(&self.buf[r], &sr);
             }
             l >>= 1;
             r >>= 1;
         }
 
-        ans
+        M::op(&sl, &sr)
     }
+
+    /// Returns the largest `r` such that `pred` holds on the aggregate of
+    /// `l..r`, assuming `pred` is monotone and holds on the identity.
+    pub fn max_right<F: FnMut(&M::T) -> bool>(&self, l: usize, mut pred: F) -> usize {
+        assert!(l <= self.size, "index out of range");
+        debug_assert!(pred(&M::identity()));
+        if l == self.size {
+            return self.size;
+        }
+
+        let mut i = l + self.offset;
+        let mut acc = M::identity();
+        loop {
+            i >>= i.trailing_zeros();
+            let next = M::op(&acc, &self.buf[i]);
+            if !pred(&next) {
+                // descend to the first leaf that breaks `pred`
+                while i < self.offset {
+                    i *= 2;
+                    let next = M::op(&acc, &self.buf[i]);
+                    if pred(&next) {
+                        acc = next;
+                        i += 1;
+                    }
+                }
+                return i - self.offset;
+            }
+            acc = next;
+            i += 1;
+            if i.is_power_of_two() {
+                return self.size;
+            }
+        }
+    }
+
+    /// Returns the smallest `l` such that `pred` holds on the aggregate of
+    /// `l..r`, assuming `pred` is monotone and holds on the identity.
+    pub fn min_left<F: FnMut(&M::T) -> bool>(&self, r: usize, mut pred: F) -> usize {
+        assert!(r <= self.size, "index out of range");
+        debug_assert!(pred(&M::identity()));
+        if r == 0 {
+            return 0;
+        }
+
+        let mut i = r + self.offset;
+        let mut acc = M::identity();
+        loop {
+            i -= 1;
+            while i > 1 && i % 2 == 1 {
+                i >>= 1;
+            }
+            let next = M::op(&self.buf[i], &acc);
+            if !pred(&next) {
+                while i < self.offset {
+                    i = i * 2 + 1;
+                    let next = M::op(&self.buf[i], &acc);
+                    if pred(&next) {
+                        acc = next;
+                        i -= 1;
+                    }
+                }
+                return i + 1 - self.offset;
+            }
+            acc = next;
+            if i.is_power_of_two() {
+                return 0;
+            }
+        }
+    }
+}
+
+impl<M: Monoid> FromIterator<M::T> for SegTree<M> {
+    fn from_iter<I: IntoIterator<Item = M::T>>(iter: I) -> Self {
+        Self::from(iter.into_iter().collect())
+    }
+}
+
+/// Resolves `range` against a length, panicking when it falls outside.
+pub(crate) fn bounds<R: RangeBounds<usize>>(range: R, len: usize) -> (usize, usize) {
+    let l = match range.start_bound() {
+        Bound::Included(&x) => x,
+        Bound::Excluded(&x) => x + 1,
+        Bound::Unbounded => 0,
+    };
+    let r = match range.end_bound() {
+        Bound::Included(&x) => x + 1,
+        Bound::Excluded(&x) => x,
+        Bound::Unbounded => len,
+    };
+    assert!(l <= r && r <= len, "range out of bounds");
+    (l, r)
 }
 
 pub struct PstSegTree<T, F>
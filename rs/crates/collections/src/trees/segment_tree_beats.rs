@@ -0,0 +1,292 @@
+use super::segment_tree::bounds;
+use std::{cmp::Ordering, ops::RangeBounds};
+
+#[derive(Clone, Copy)]
+struct Node {
+    sum: i64,
+    // largest value, the largest one below it and how often the first occurs
+    max: i64,
+    max2: i64,
+    max_cnt: i64,
+    min: i64,
+    min2: i64,
+    min_cnt: i64,
+    len: i64,
+    // pending addition for the children
+    add: i64,
+}
+
+impl Node {
+    fn leaf(x: i64) -> Self {
+        Self {
+            sum: x,
+            max: x,
+            max2: i64::MIN,
+            max_cnt: 1,
+            min: x,
+            min2: i64::MAX,
+            min_cnt: 1,
+            len: 1,
+            add: 0,
+        }
+    }
+
+    fn merge(l: &Self, r: &Self) -> Self {
+        let (max, max2, max_cnt) = match l.max.cmp(&r.max) {
+            Ordering::Equal => (l.max, l.max2.max(r.max2), l.max_cnt + r.max_cnt),
+            Ordering::Greater => (l.max, l.max2.max(r.max), l.max_cnt),
+            Ordering::Less => (r.max, r.max2.max(l.max), r.max_cnt),
+        };
+        let (min, min2, min_cnt) = match l.min.cmp(&r.min) {
+            Ordering::Equal => (l.min, l.min2.min(r.min2), l.min_cnt + r.min_cnt),
+            Ordering::Less => (l.min, l.min2.min(r.min), l.min_cnt),
+            Ordering::Greater => (r.min, r.min2.min(l.min), r.min_cnt),
+        };
+        Self {
+            sum: l.sum + r.sum,
+            max,
+            max2,
+            max_cnt,
+            min,
+            min2,
+            min_cnt,
+            len: l.len + r.len,
+            add: 0,
+        }
+    }
+
+    fn add(&mut self, x: i64) {
+        self.sum += x * self.len;
+        self.max += x;
+        self.min += x;
+        if self.max2 != i64::MIN {
+            self.max2 += x;
+        }
+        if self.min2 != i64::MAX {
+            self.min2 += x;
+        }
+        self.add += x;
+    }
+
+    /// Lowers the maximum to `x`, needs `max2 < x`.
+    fn cap_max(&mut self, x: i64) {
+        if x >= self.max {
+            return;
+        }
+        self.sum += (x - self.max) * self.max_cnt;
+        if self.min == self.max {
+            self.min = x;
+        } else if self.min2 == self.max {
+            self.min2 = x;
+        }
+        self.max = x;
+    }
+
+    /// Raises the minimum to `x`, needs `min2 > x`.
+    fn cap_min(&mut self, x: i64) {
+        if x <= self.min {
+            return;
+        }
+        self.sum += (x - self.min) * self.min_cnt;
+        if self.max == self.min {
+            self.max = x;
+        } else if self.max2 == self.min {
+            self.max2 = x;
+        }
+        self.min = x;
+    }
+}
+
+/// Segment Tree Beats: range `chmin`, `chmax` and `add` with range sum,
+/// min and max, in amortized O(log² n).
+///
+/// `chmin(x)` only tags a node when `x` lies between its largest and second
+/// largest value, as then only the maxima change. Otherwise it recurses,
+/// and every such step merges two distinct values, which bounds the work.
+/// `chmax` is the mirror image.
+pub struct SegTreeBeats {
+    nodes: Vec<Node>,
+    size: usize,
+}
+
+impl SegTreeBeats {
+    /// `size` zeros.
+    pub fn new(size: usize) -> Self {
+        Self::from(vec![0; size])
+    }
+
+    pub fn from(v: Vec<i64>) -> Self {
+        let size = v.len();
+        let mut st = Self {
+            nodes: vec![Node::leaf(0); size.max(1) * 4],
+            size,
+        };
+        if size > 0 {
+            st.build(1, 0, size, &v);
+        }
+        st
+    }
+
+    pub fn len(&self) -> usize {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    /// Sets every element in `range` to `min(a[i], x)`.
+    pub fn chmin<R: RangeBounds<usize>>(&mut self, range: R, x: i64) {
+        let (l, r) = bounds(range, self.size);
+        if l < r {
+            self.chmin_node(1, 0, self.size, l, r, x);
+        }
+    }
+
+    /// Sets every element in `range` to `max(a[i], x)`.
+    pub fn chmax<R: RangeBounds<usize>>(&mut self, range: R, x: i64) {
+        let (l, r) = bounds(range, self.size);
+        if l < r {
+            self.chmax_node(1, 0, self.size, l, r, x);
+        }
+    }
+
+    pub fn add<R: RangeBounds<usize>>(&mut self, range: R, x: i64) {
+        let (l, r) = bounds(range, self.size);
+        if l < r {
+            self.add_node(1, 0, self.size, l, r, x);
+        }
+    }
+
+    /// Sum over `range`, 0 when it is empty.
+    pub fn sum<R: RangeBounds<usize>>(&mut self, range: R) -> i64 {
+        self.fold(range, 0, |n| n.sum, |a, b| a + b)
+    }
+
+    /// Minimum over `range`, `i64::MAX` when it is empty.
+    pub fn min<R: RangeBounds<usize>>(&mut self, range: R) -> i64 {
+        self.fold(range, i64::MAX, |n| n.min, i64::min)
+    }
+
+    /// Maximum over `range`, `i64::MIN` when it is empty.
+    pub fn max<R: RangeBounds<usize>>(&mut self, range: R) -> i64 {
+        self.fold(range, i64::MIN, |n| n.max, i64::max)
+    }
+
+    fn fold<R, G, F>(&mut self, range: R, id: i64, get: G, op: F) -> i64
+    where
+        R: RangeBounds<usize>,
+        G: Fn(&Node) -> i64 + Copy,
+        F: Fn(i64, i64) -> i64 + Copy,
+    {
+        let (l, r) = bounds(range, self.size);
+        if l == r {
+            return id;
+        }
+        self.fold_node(1, 0, self.size, l, r, id, get, op)
+    }
+
+    fn build(&mut self, k: usize, lo: usize, hi: usize, v: &[i64]) {
+        if hi - lo == 1 {
+            self.nodes[k] = Node::leaf(v[lo]);
+            return;
+        }
+        let mid = (lo + hi) / 2;
+        self.build(k * 2, lo, mid, v);
+        self.build(k * 2 + 1, mid, hi, v);
+        self.pull(k);
+    }
+
+    fn pull(&mut self, k: usize) {
+        self.nodes[k] = Node::merge(&self.nodes[k * 2], &self.nodes[k * 2 + 1]);
+    }
+
+    fn push(&mut self, k: usize) {
+        let nd = self.nodes[k];
+        for c in [k * 2, k * 2 + 1] {
+            let child = &mut self.nodes[c];
+            if nd.add != 0 {
+                child.add(nd.add);
+            }
+            // a child past the bounds of its parent still owes a cap
+            child.cap_max(nd.max);
+            child.cap_min(nd.min);
+        }
+        self.nodes[k].add = 0;
+    }
+
+    fn chmin_node(&mut self, k: usize, lo: usize, hi: usize, l: usize, r: usize, x: i64) {
+        if r <= lo || hi <= l || self.nodes[k].max <= x {
+            return;
+        }
+        if l <= lo && hi <= r && self.nodes[k].max2 < x {
+            self.nodes[k].cap_max(x);
+            return;
+        }
+        let mid = (lo + hi) / 2;
+        self.push(k);
+        self.chmin_node(k * 2, lo, mid, l, r, x);
+        self.chmin_node(k * 2 + 1, mid, hi, l, r, x);
+        self.pull(k);
+    }
+
+    fn chmax_node(&mut self, k: usize, lo: usize, hi: usize, l: usize, r: usize, x: i64) {
+        if r <= lo || hi <= l || self.nodes[k].min >= x {
+            return;
+        }
+        if l <= lo && hi <= r && self.nodes[k].min2 > x {
+            self.nodes[k].cap_min(x);
+            return;
+        }
+        let mid = (lo + hi) / 2;
+        self.push(k);
+        self.chmax_node(k * 2, lo, mid, l, r, x);
+        self.chmax_node(k * 2 + 1, mid, hi, l, r, x);
+        self.pull(k);
+    }
+
+    fn add_node(&mut self, k: usize, lo: usize, hi: usize, l: usize, r: usize, x: i64) {
+        if r <= lo || hi <= l {
+            return;
+        }
+        if l <= lo && hi <= r {
+            self.nodes[k].add(x);
+            return;
+        }
+        let mid = (lo + hi) / 2;
+        self.push(k);
+        self.add_node(k * 2, lo, mid, l, r, x);
+        self.add_node(k * 2 + 1, mid, hi, l, r, x);
+        self.pull(k);
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn fold_node<G, F>(
+        &mut self,
+        k: usize,
+        lo: usize,
+        hi: usize,
+        l: usize,
+        r: usize,
+        id: i64,
+        get: G,
+        op: F,
+    ) -> i64
+    where
+        G: Fn(&Node) -> i64 + Copy,
+        F: Fn(i64, i64) -> i64 + Copy,
+    {
+        if r <= lo || hi <= l {
+            return id;
+        }
+        if l <= lo && hi <= r {
+            return get(&self.nodes[k]);
+        }
+        let mid = (lo + hi) / 2;
+        self.push(k);
+        op(
+            self.fold_node(k * 2, lo, mid, l, r, id, get, op),
+            self.fold_node(k * 2 + 1, mid, hi, l, r, id, get, op),
+        )
+    }
+}
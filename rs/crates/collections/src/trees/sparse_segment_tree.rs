@@ -0,0 +1,548 @@
+use super::lazy_segment_tree::Action;
+use super::monoid::Monoid;
+use std::ops::{Bound, RangeBounds};
+
+/// The empty subtree, every element `M::identity()`. It lives at index 0
+/// and is never written, so missing children need no allocation.
+const NIL: u32 = 0;
+
+struct Node<T, F> {
+    val: T,
+    // map still owed to the children, already applied to `val`
+    lazy: Option<F>,
+    left: u32,
+    right: u32,
+}
+
+/// Segment tree over the index range `lo..hi` of `i64`, allocating nodes
+/// only along the paths that are written. Memory is O(q log(hi - lo)) for
+/// `q` updates, so no coordinate compression is needed.
+///
+/// Nodes live in an arena and address each other by `u32` index. `A` adds
+/// lazy range updates, `()` leaves them out. Trees that are merged or
+/// split must share an arena, see [`SegForest`].
+pub struct SparseSegTree<M: Monoid, A: Action<M> = ()> {
+    arena: Arena<M, A>,
+    root: u32,
+}
+
+impl<M: Monoid, A: Action<M>> SparseSegTree<M, A> {
+    /// Every index in `lo..hi` starts out as `M::identity()`.
+    pub fn new(lo: i64, hi: i64) -> Self {
+        Self {
+            arena: Arena::new(lo, hi),
+            root: NIL,
+        }
+    }
+
+    /// Number of allocated nodes.
+    pub fn node_count(&self) -> usize {
+        self.arena.node_count()
+    }
+
+    pub fn get(&self, i: i64) -> M::T {
+        self.arena.get(self.root, i)
+    }
+
+    pub fn set(&mut self, i: i64, val: M::T) {
+        self.root = self.arena.set(self.root, i, val);
+    }
+
+    /// Aggregate over `range`, `M::identity()` when it is empty.
+    pub fn query<R: RangeBounds<i64>>(&self, range: R) -> M::T {
+        self.arena.query(self.root, range)
+    }
+
+    /// Applies `f` to every element in `range`.
+    pub fn apply<R: RangeBounds<i64>>(&mut self, range: R, f: A::F) {
+        self.root = self.arena.apply(self.root, range, &f);
+    }
+
+    /// Returns the largest `r` such that `pred` holds on the aggregate of
+    /// `l..r`, assuming `pred` is monotone and holds on the identity.
+    pub fn max_right<F: FnMut(&M::T) -> bool>(&self, l: i64, pred: F) -> i64 {
+        self.arena.max_right(self.root, l, pred)
+    }
+
+    /// Returns the smallest `l` such that `pred` holds on the aggregate of
+    /// `l..r`, assuming `pred` is monotone and holds on the identity.
+    pub fn min_left<F: FnMut(&M::T) -> bool>(&self, r: i64, pred: F) -> i64 {
+        self.arena.min_left(self.root, r, pred)
+    }
+}
+
+/// Handle to one tree of a [`SegForest`]. It is not `Copy`, so `merge`,
+/// `split` and `clear` consume the trees they take.
+#[derive(Debug, PartialEq, Eq)]
+pub struct SegRoot(u32);
+
+/// Sparse segment trees over the same range `lo..hi` sharing one node
+/// arena, for merging per-subtree trees in tree DP.
+///
+/// The nodes are those of [`SparseSegTree`], which is a forest holding a
+/// single tree. Merging needs both trees in the same arena, so mergeable
+/// trees are handed out as [`SegRoot`]s of one forest rather than as
+/// separate `SparseSegTree`s.
+///
+/// Merging all the trees built from `n` point writes takes O(n log(hi - lo))
+/// in total, since every merge step frees a node. Freed nodes are reused by
+/// later writes.
+pub struct SegForest<M: Monoid, A: Action<M> = ()> {
+    arena: Arena<M, A>,
+}
+
+impl<M: Monoid, A: Action<M>> SegForest<M, A> {
+    pub fn new(lo: i64, hi: i64) -> Self {
+        Self {
+            arena: Arena::new(lo, hi),
+        }
+    }
+
+    /// A tree with every element `M::identity()`, it takes no node.
+    pub fn empty(&self) -> SegRoot {
+        SegRoot(NIL)
+    }
+
+    /// Number of nodes in use across all trees.
+    pub fn node_count(&self) -> usize {
+        self.arena.node_count()
+    }
+
+    pub fn get(&self, t: &SegRoot, i: i64) -> M::T {
+        self.arena.get(t.0, i)
+    }
+
+    pub fn set(&mut self, t: &mut SegRoot, i: i64, val: M::T) {
+        t.0 = self.arena.set(t.0, i, val);
+    }
+
+    /// Aggregate of tree `t` over `range`.
+    pub fn query<R: RangeBounds<i64>>(&self, t: &SegRoot, range: R) -> M::T {
+        self.arena.query(t.0, range)
+    }
+
+    /// Applies `f` to every element of tree `t` in `range`.
+    pub fn apply<R: RangeBounds<i64>>(&mut self, t: &mut SegRoot, range: R, f: A::F) {
+        t.0 = self.arena.apply(t.0, range, &f);
+    }
+
+    /// Returns the largest `r` such that `pred` holds on the aggregate of
+    /// `l..r` in tree `t`, assuming `pred` is monotone and holds on the
+    /// identity.
+    pub fn max_right<F: FnMut(&M::T) -> bool>(&self, t: &SegRoot, l: i64, pred: F) -> i64 {
+        self.arena.max_right(t.0, l, pred)
+    }
+
+    /// Mirror of [`Self::max_right`], the smallest `l` such that `pred`
+    /// holds on the aggregate of `l..r` in tree `t`.
+    pub fn min_left<F: FnMut(&M::T) -> bool>(&self, t: &SegRoot, r: i64, pred: F) -> i64 {
+        self.arena.min_left(t.0, r, pred)
+    }
+
+    /// Merges `b` into `a`, combining the elements both trees hold with
+    /// `combine_leaf(a, b)`. Indices held by one tree only keep its value.
+    pub fn merge<F>(&mut self, a: SegRoot, b: SegRoot, mut combine_leaf: F) -> SegRoot
+    where
+        F: FnMut(&M::T, &M::T) -> M::T,
+    {
+        debug_assert!(a.0 != b.0 || a.0 == NIL, "merging a tree with itself");
+        let (lo, hi) = (self.arena.lo, self.arena.hi);
+        SegRoot(self.arena.merge_node(a.0, b.0, lo, hi, &mut combine_leaf))
+    }
+
+    /// Splits `t` into the indices less than `at` and the rest.
+    pub fn split(&mut self, t: SegRoot, at: i64) -> (SegRoot, SegRoot) {
+        let (lo, hi) = (self.arena.lo, self.arena.hi);
+        assert!(lo <= at && at <= hi, "index out of range");
+        let (a, b) = self.arena.split_node(t.0, lo, hi, at);
+        (SegRoot(a), SegRoot(b))
+    }
+
+    /// Returns the nodes of `t` to the arena.
+    pub fn clear(&mut self, t: SegRoot) {
+        let mut stk = vec![t.0];
+        while let Some(k) = stk.pop() {
+            if k != NIL {
+                let nd = self.arena.node(k);
+                stk.extend([nd.left, nd.right]);
+                self.arena.release(k);
+            }
+        }
+    }
+}
+
+/// Resolves `range` against `lo..hi`, panicking when it falls outside.
+fn bounds<R: RangeBounds<i64>>(range: R, lo: i64, hi: i64) -> (i64, i64) {
+    let l = match range.start_bound() {
+        Bound::Included(&x) => x,
+        Bound::Excluded(&x) => x + 1,
+        Bound::Unbounded => lo,
+    };
+    let r = match range.end_bound() {
+        Bound::Included(&x) => x + 1,
+        Bound::Excluded(&x) => x,
+        Bound::Unbounded => hi,
+    };
+    assert!(lo <= l && l <= r && r <= hi, "range out of bounds");
+    (l, r)
+}
+
+/// Nodes of any number of trees over `lo..hi`, each named by its root.
+struct Arena<M: Monoid, A: Action<M>> {
+    nodes: Vec<Node<M::T, A::F>>,
+    free: Vec<u32>,
+    lo: i64,
+    hi: i64,
+}
+
+impl<M: Monoid, A: Action<M>> Arena<M, A> {
+    fn new(lo: i64, hi: i64) -> Self {
+        assert!(lo <= hi, "empty range");
+        assert!(hi.checked_sub(lo).is_some(), "range too wide");
+        let nil = Node {
+            val: M::identity(),
+            lazy: None,
+            left: NIL,
+            right: NIL,
+        };
+        Self {
+            nodes: vec![nil],
+            free: vec![],
+            lo,
+            hi,
+        }
+    }
+
+    fn node_count(&self) -> usize {
+        self.nodes.len() - 1 - self.free.len()
+    }
+
+    fn get(&self, root: u32, i: i64) -> M::T {
+        assert!(self.lo <= i && i < self.hi, "index out of range");
+        self.query(root, i..=i)
+    }
+
+    fn set(&mut self, root: u32, i: i64, val: M::T) -> u32 {
+        assert!(self.lo <= i && i < self.hi, "index out of range");
+        self.set_node(root, self.lo, self.hi, i, val)
+    }
+
+    fn query<R: RangeBounds<i64>>(&self, root: u32, range: R) -> M::T {
+        let (l, r) = bounds(range, self.lo, self.hi);
+        if l == r {
+            return M::identity();
+        }
+        self.query_node(root, self.lo, self.hi, l, r, &None)
+    }
+
+    fn apply<R: RangeBounds<i64>>(&mut self, root: u32, range: R, f: &A::F) -> u32 {
+        let (l, r) = bounds(range, self.lo, self.hi);
+        if l == r {
+            return root;
+        }
+        self.apply_node(root, self.lo, self.hi, l, r, f)
+    }
+
+    fn max_right<F: FnMut(&M::T) -> bool>(&self, root: u32, l: i64, mut pred: F) -> i64 {
+        assert!(self.lo <= l && l <= self.hi, "index out of range");
+        debug_assert!(pred(&M::identity()));
+        let mut acc = M::identity();
+        let (lo, hi) = (self.lo, self.hi);
+        self.max_right_node(root, lo, hi, l, &None, &mut acc, &mut pred)
+            .unwrap_or(hi)
+    }
+
+    fn min_left<F: FnMut(&M::T) -> bool>(&self, root: u32, r: i64, mut pred: F) -> i64 {
+        assert!(self.lo <= r && r <= self.hi, "index out of range");
+        debug_assert!(pred(&M::identity()));
+        let mut acc = M::identity();
+        let (lo, hi) = (self.lo, self.hi);
+        self.min_left_node(root, lo, hi, r, &None, &mut acc, &mut pred)
+            .map_or(lo, |i| i + 1)
+    }
+
+    fn alloc(&mut self, val: M::T, lazy: Option<A::F>) -> u32 {
+        let nd = Node {
+            val,
+            lazy,
+            left: NIL,
+            right: NIL,
+        };
+        match self.free.pop() {
+            Some(k) => {
+                self.nodes[k as usize] = nd;
+                k
+            }
+            None => {
+                self.nodes.push(nd);
+                (self.nodes.len() - 1) as u32
+            }
+        }
+    }
+
+    fn release(&mut self, k: u32) {
+        debug_assert_ne!(k, NIL);
+        self.free.push(k);
+    }
+
+    fn node(&self, k: u32) -> &Node<M::T, A::F> {
+        &self.nodes[k as usize]
+    }
+
+    fn node_mut(&mut self, k: u32) -> &mut Node<M::T, A::F> {
+        debug_assert_ne!(k, NIL);
+        &mut self.nodes[k as usize]
+    }
+
+    /// `f` after `g`, either may be missing.
+    fn compose(f: &Option<A::F>, g: &Option<A::F>) -> Option<A::F> {
+        match (f, g) {
+            (Some(f), Some(g)) => Some(A::compose(f, g)),
+            _ => f.clone().or_else(|| g.clone()),
+        }
+    }
+
+    /// Value of node `k` covering `lo..hi`, with `pending` applied on top.
+    fn value(&self, k: u32, lo: i64, hi: i64, pending: &Option<A::F>) -> M::T {
+        let val = &self.node(k).val;
+        match pending {
+            Some(f) => A::apply(f, val, (hi - lo) as usize),
+            None => val.clone(),
+        }
+    }
+
+    /// Applies `f` to the whole of node `k`, allocating it if it is empty.
+    fn apply_all(&mut self, k: u32, lo: i64, hi: i64, f: &A::F) -> u32 {
+        let val = A::apply(f, &self.node(k).val, (hi - lo) as usize);
+        if k == NIL {
+            return self.alloc(val, Some(f.clone()));
+        }
+        let nd = self.node_mut(k);
+        nd.val = val;
+        nd.lazy = Some(match &nd.lazy {
+            Some(g) => A::compose(f, g),
+            None => f.clone(),
+        });
+        k
+    }
+
+    /// Pushes the pending map of `k` to its children, `k` must not be empty.
+    fn push(&mut self, k: u32, lo: i64, mid: i64, hi: i64) {
+        if let Some(f) = self.node_mut(k).lazy.take() {
+            let (left, right) = (self.node(k).left, self.node(k).right);
+            let left = self.apply_all(left, lo, mid, &f);
+            let right = self.apply_all(right, mid, hi, &f);
+            let nd = self.node_mut(k);
+            nd.left = left;
+            nd.right = right;
+        }
+    }
+
+    fn pull(&mut self, k: u32) {
+        let nd = self.node(k);
+        let val = M::op(&self.node(nd.left).val, &self.node(nd.right).val);
+        self.node_mut(k).val = val;
+    }
+
+    fn set_node(&mut self, k: u32, lo: i64, hi: i64, i: i64, val: M::T) -> u32 {
+        let k = if k == NIL {
+            self.alloc(M::identity(), None)
+        } else {
+            k
+        };
+        if hi - lo == 1 {
+            self.node_mut(k).val = val;
+            return k;
+        }
+
+        let mid = lo + (hi - lo) / 2;
+        self.push(k, lo, mid, hi);
+        if i < mid {
+            let left = self.set_node(self.node(k).left, lo, mid, i, val);
+            self.node_mut(k).left = left;
+        } else {
+            let right = self.set_node(self.node(k).right, mid, hi, i, val);
+            self.node_mut(k).right = right;
+        }
+        self.pull(k);
+        k
+    }
+
+    fn apply_node(&mut self, k: u32, lo: i64, hi: i64, l: i64, r: i64, f: &A::F) -> u32 {
+        if r <= lo || hi <= l {
+            return k;
+        }
+        if l <= lo && hi <= r {
+            return self.apply_all(k, lo, hi, f);
+        }
+
+        let k = if k == NIL {
+            self.alloc(M::identity(), None)
+        } else {
+            k
+        };
+        let mid = lo + (hi - lo) / 2;
+        self.push(k, lo, mid, hi);
+        let left = self.apply_node(self.node(k).left, lo, mid, l, r, f);
+        let right = self.apply_node(self.node(k).right, mid, hi, l, r, f);
+        let nd = self.node_mut(k);
+        nd.left = left;
+        nd.right = right;
+        self.pull(k);
+        k
+    }
+
+    fn query_node(&self, k: u32, lo: i64, hi: i64, l: i64, r: i64, pending: &Option<A::F>) -> M::T {
+        if r <= lo || hi <= l {
+            return M::identity();
+        }
+        if l <= lo && hi <= r {
+            return self.value(k, lo, hi, pending);
+        }
+
+        let nd = self.node(k);
+        let pending = Self::compose(pending, &nd.lazy);
+        let mid = lo + (hi - lo) / 2;
+        M::op(
+            &self.query_node(nd.left, lo, mid, l, r, &pending),
+            &self.query_node(nd.right, mid, hi, l, r, &pending),
+        )
+    }
+
+    /// Folds the part of `k` from `l` on into `acc` while `pred` holds,
+    /// returns the index where it first fails.
+    #[allow(clippy::too_many_arguments)]
+    fn max_right_node<F: FnMut(&M::T) -> bool>(
+        &self,
+        k: u32,
+        lo: i64,
+        hi: i64,
+        l: i64,
+        pending: &Option<A::F>,
+        acc: &mut M::T,
+        pred: &mut F,
+    ) -> Option<i64> {
+        if hi <= l {
+            return None;
+        }
+        if l <= lo {
+            let next = M::op(acc, &self.value(k, lo, hi, pending));
+            if pred(&next) {
+                *acc = next;
+                return None;
+            }
+            if hi - lo == 1 {
+                return Some(lo);
+            }
+        }
+
+        let nd = self.node(k);
+        let pending = Self::compose(pending, &nd.lazy);
+        let mid = lo + (hi - lo) / 2;
+        self.max_right_node(nd.left, lo, mid, l, &pending, acc, pred)
+            .or_else(|| self.max_right_node(nd.right, mid, hi, l, &pending, acc, pred))
+    }
+
+    /// Mirror of [`Self::max_right_node`], folding the part before `r`
+    /// from the right.
+    #[allow(clippy::too_many_arguments)]
+    fn min_left_node<F: FnMut(&M::T) -> bool>(
+        &self,
+        k: u32,
+        lo: i64,
+        hi: i64,
+        r: i64,
+        pending: &Option<A::F>,
+        acc: &mut M::T,
+        pred: &mut F,
+    ) -> Option<i64> {
+        if r <= lo {
+            return None;
+        }
+        if hi <= r {
+            let next = M::op(&self.value(k, lo, hi, pending), acc);
+            if pred(&next) {
+                *acc = next;
+                return None;
+            }
+            if hi - lo == 1 {
+                return Some(lo);
+            }
+        }
+
+        let nd = self.node(k);
+        let pending = Self::compose(pending, &nd.lazy);
+        let mid = lo + (hi - lo) / 2;
+        self.min_left_node(nd.right, mid, hi, r, &pending, acc, pred)
+            .or_else(|| self.min_left_node(nd.left, lo, mid, r, &pending, acc, pred))
+    }
+
+    /// Merges the trees `a` and `b` covering `lo..hi` into `a`, freeing `b`.
+    fn merge_node<F>(&mut self, a: u32, b: u32, lo: i64, hi: i64, f: &mut F) -> u32
+    where
+        F: FnMut(&M::T, &M::T) -> M::T,
+    {
+        if a == NIL || b == NIL {
+            return a.max(b);
+        }
+        if hi - lo == 1 {
+            // a leaf's pending map is already in its value
+            let val = f(&self.node(a).val, &self.node(b).val);
+            let nd = self.node_mut(a);
+            nd.val = val;
+            nd.lazy = None;
+        } else {
+            let mid = lo + (hi - lo) / 2;
+            self.push(a, lo, mid, hi);
+            self.push(b, lo, mid, hi);
+            let (na, nb) = (self.node(a), self.node(b));
+            let (al, ar, bl, br) = (na.left, na.right, nb.left, nb.right);
+            let left = self.merge_node(al, bl, lo, mid, f);
+            let right = self.merge_node(ar, br, mid, hi, f);
+            let nd = self.node_mut(a);
+            nd.left = left;
+            nd.right = right;
+            self.pull(a);
+        }
+        self.release(b);
+        a
+    }
+
+    fn split_node(&mut self, k: u32, lo: i64, hi: i64, at: i64) -> (u32, u32) {
+        if k == NIL {
+            return (NIL, NIL);
+        }
+        if at <= lo {
+            return (NIL, k);
+        }
+        if hi <= at {
+            return (k, NIL);
+        }
+
+        // `k` keeps the left part, a new node takes the right one
+        let mid = lo + (hi - lo) / 2;
+        self.push(k, lo, mid, hi);
+        let nd = self.node(k);
+        let (left, right) = (nd.left, nd.right);
+        let (l1, l2) = self.split_node(left, lo, mid, at);
+        let (r1, r2) = self.split_node(right, mid, hi, at);
+        let b = self.alloc(M::identity(), None);
+        for (x, cl, cr) in [(k, l1, r1), (b, l2, r2)] {
+            let nd = self.node_mut(x);
+            nd.left = cl;
+            nd.right = cr;
+            self.pull(x);
+        }
+
+        let prune = |s: &mut Self, x: u32| {
+            let nd = s.node(x);
+            if nd.left == NIL && nd.right == NIL {
+                s.release(x);
+                NIL
+            } else {
+                x
+            }
+        };
+        (prune(self, k), prune(self, b))
+    }
+}
@@ -1,14 +1,22 @@
 #![allow(dead_code)]
 
 use ptr::NonNull;
-use std::{cmp::Ordering, marker::PhantomData, mem, ops::Add, ptr, todo};
+use std::{
+    cell::Cell,
+    cmp::Ordering,
+    marker::PhantomData,
+    mem,
+    ops::{Add, Bound, RangeBounds},
+    ptr, todo,
+};
 
 use super::binary_tree;
+use crate::invariant::InvariantError;
 
 type Link<K, V> = Option<NonNull<Node<K, V>>>;
 
 #[derive(Debug)]
-struct Node<K, V> {
+pub struct Node<K, V> {
     key: K,
     val: V,
     left: Link<K, V>,
@@ -16,10 +24,19 @@ struct Node<K, V> {
     size: usize,
 }
 
-impl_debug!(SplayTreeMap);
+impl_debug!(SplayTreeMap, |t: &Self| t.root.get());
+impl_entry!();
 
+pub struct VacantEntry<'a, K, V> {
+    key: K,
+    map: &'a mut SplayTreeMap<K, V>,
+}
+
+/// Lookups splay through a `Cell`, so `get` only needs `&self`. Splaying
+/// relinks nodes through raw pointers without moving them or forming
+/// `&mut Node`, so returned references stay valid.
 pub struct SplayTreeMap<K, V> {
-    root: Link<K, V>,
+    root: Cell<Link<K, V>>,
     _marker: PhantomData<(K, V)>
 }
 
@@ -39,23 +56,31 @@ impl<K, V> Node<K, V> {
         NonNull::from(Box::leak(b))
     }
 
-    fn set_left(&mut self, node: Link<K, V>) {
-        self.left = node;
-        unsafe {
-            self.refresh_size();
-        }
-    }
+    /// Writes go through raw field pointers and never form `&mut Node`,
+    /// since `&self` lookups relink nodes a caller may still borrow from.
+    unsafe fn write_left(this: NonNull<Self>, node: Link<K, V>) { unsafe {
+        ptr::addr_of_mut!((*this.as_ptr()).left).write(node);
+    }}
 
-    fn set_right(&mut self, node: Link<K, V>) {
-        self.right = node;
-        unsafe {
-            self.refresh_size();
-        }
-    }
+    unsafe fn write_right(this: NonNull<Self>, node: Link<K, V>) { unsafe {
+        ptr::addr_of_mut!((*this.as_ptr()).right).write(node);
+    }}
+
+    unsafe fn set_left(this: NonNull<Self>, node: Link<K, V>) { unsafe {
+        Self::write_left(this, node);
+        Self::refresh_size(this);
+    }}
+
+    unsafe fn set_right(this: NonNull<Self>, node: Link<K, V>) { unsafe {
+        Self::write_right(this, node);
+        Self::refresh_size(this);
+    }}
 
-    unsafe fn refresh_size(&mut self) { unsafe {
+    unsafe fn refresh_size(this: NonNull<Self>) { unsafe {
         let f = |u: Link<K, V>| u.map_or(0, |x| x.as_ref().size);
-        self.size = 1 + f(self.left) + f(self.right);
+        let n = this.as_ref();
+        let size = 1 + f(n.left) + f(n.right);
+        ptr::addr_of_mut!((*this.as_ptr()).size).write(size);
     }}
 
     fn take_left(&mut self) -> Link<K, V> {
@@ -71,36 +96,6 @@ impl<K, V> Node<K, V> {
     fn is_leaf(&self) -> bool {
         self.left.is_none() && self.right.is_none()
     }
-
-    unsafe fn left_rotate(&mut self) -> Link<K, V> { unsafe {
-        // left rotate a red link
-        //          <1>                   <2>
-        //        /    \\               //    \
-        //       *      <2>    ==>     <1>     *
-        //             /   \          /   \
-        //            *     *        *     *
-        self.take_right().map(|mut par| {
-            self.set_right(par.as_mut().take_left());
-            par.as_mut()
-                .set_left(Some(NonNull::new_unchecked(self as *mut Self)));
-            par
-        })
-    }}
-
-    unsafe fn right_rotate(&mut self) -> Link<K, V> { unsafe {
-        // right rotate a red link
-        //            <1>               <2>
-        //          //    \           /    \\
-        //         <2>     *   ==>   *      <1>
-        //        /   \                    /   \
-        //       *     *                  *     *
-        self.take_left().map(|mut par| {
-            self.set_left(par.as_mut().take_right());
-            par.as_mut()
-                .set_right(Some(NonNull::new_unchecked(self as *mut Self)));
-            par
-        })
-    }}
 }
 
 impl<K: Ord, V> Default for SplayTreeMap<K, V> {
@@ -111,67 +106,259 @@ impl<K: Ord, V> Default for SplayTreeMap<K, V> {
 
 impl<K: Ord, V> SplayTreeMap<K, V> {
     pub fn new() -> Self {
-        Self { root: None, _marker: PhantomData }
+        Self { root: Cell::new(None), _marker: PhantomData }
     }
 
     pub fn len(&self) -> usize {
-        self.root.map_or(0, |x| unsafe { x.as_ref().size })
+        self.root.get().map_or(0, |x| unsafe { x.as_ref().size })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.get().is_none()
     }
 
     pub fn insert(&mut self, key: K, val: V) {
-        self.root = unsafe { Self::insert_node(self.root, key, val) };
+        match self.entry(key) {
+            Entry::Occupied(mut e) => {
+                e.insert(val);
+            }
+            Entry::Vacant(e) => {
+                e.insert(val);
+            }
+        }
     }
 
-    unsafe fn insert_node(node: Link<K, V>, key: K, val: V) -> Link<K, V> { unsafe {
-        if let Some(mut nd) = node {
-            match nd.as_ref().key.cmp(&key) {
-                Ordering::Equal => {
-                    nd.as_mut().val = val;
-                    node
-                }
-                Ordering::Greater => {
-                    let root = Self::insert_node(nd.as_mut().left, key, val);
-                    nd.as_mut().set_left(root);
-                    nd.as_mut().right_rotate()
-                }
-                Ordering::Less => {
-                    let root = Self::insert_node(nd.as_mut().right, key, val);
-                    nd.as_mut().set_right(root);
-                    nd.as_mut().left_rotate()
-                }
+    /// Splays `key` (or the last node on its search path) to the root.
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V> {
+        unsafe {
+            self.root.set(Self::splay(self.root.get(), &key));
+            debug_invariants!(self);
+            match self.root.get() {
+                Some(r) if r.as_ref().key == key => Entry::Occupied(OccupiedEntry::new(r)),
+                _ => Entry::Vacant(VacantEntry { key, map: self }),
             }
-        } else {
-            Some(Node::new(key, val).wrap())
         }
-    }}
+    }
 
-    pub fn get(&mut self, key: &K) -> Option<&V> {
+    /// Splays `key` to the root, taking only `&self`.
+    pub fn get(&self, key: &K) -> Option<&V> {
         unsafe {
-            self.root = Self::splay(self.root, key);
+            self.root.set(Self::splay(self.root.get(), key));
+            debug_invariants!(self);
             self.root
+                .get()
                 .filter(|x| &x.as_ref().key == key)
                 .map(|x| &(*x.as_ptr()).val)
         }
     }
 
-    unsafe fn splay(node: Link<K, V>, key: &K) -> Link<K, V> { unsafe {
-        if let Some(mut nd) = node {
-            match nd.as_ref().key.cmp(key) {
-                Ordering::Equal => node,
-                Ordering::Greater => {
-                    let root = Self::splay(nd.as_mut().left, key);
-                    nd.as_mut().set_left(root);
-                    nd.as_mut().right_rotate().or(node)
+    /// Returns the `k`-th smallest entry (0-indexed) and splays it.
+    pub fn nth(&self, k: usize) -> Option<(&K, &V)> {
+        if k >= self.len() {
+            return None;
+        }
+        let mut k = k;
+        unsafe {
+            let root = Self::splay_by(self.root.get(), |n| {
+                let l = Self::size_of(n.left);
+                let c = k.cmp(&l);
+                if c == Ordering::Greater {
+                    k -= l + 1;
                 }
+                c
+            });
+            self.root.set(root);
+            root.map(|x| (&(*x.as_ptr()).key, &(*x.as_ptr()).val))
+        }
+    }
+
+    /// Returns the number of keys strictly less than `key`, splaying it.
+    pub fn rank(&self, key: &K) -> usize {
+        unsafe {
+            self.root.set(Self::splay(self.root.get(), key));
+            self.root.get().map_or(0, |r| {
+                let r = r.as_ref();
+                Self::size_of(r.left) + (r.key < *key) as usize
+            })
+        }
+    }
+
+    pub fn iter(&self) -> Range<'_, K, V> {
+        self.range(..)
+    }
+
+    /// Iterates over the entries whose keys fall into `range`, from either end.
+    ///
+    /// Both bounds are splayed up front and every step splays the next
+    /// entry, so nearby scans stay cheap and lookups through `&self` may
+    /// be interleaved with the iteration.
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Range<'_, K, V> {
+        unsafe {
+            let back = self.last_in(range.end_bound());
+            let front = self.first_in(range.start_bound());
+            let len = match (front, back) {
+                (Some((_, i)), Some((_, j))) if i <= j => j - i + 1,
+                _ => 0,
+            };
+            Range {
+                map: self,
+                front: front.map(|x| x.0),
+                back: back.map(|x| x.0),
+                len,
+            }
+        }
+    }
+
+    fn size_of(node: Link<K, V>) -> usize {
+        node.map_or(0, |x| unsafe { x.as_ref().size })
+    }
+
+    /// Splays the smallest key within `bound`, returns it with its rank.
+    unsafe fn first_in(&self, bound: Bound<&K>) -> Option<(NonNull<Node<K, V>>, usize)> { unsafe {
+        let root = match bound {
+            Bound::Included(k) => Self::splay_by(self.root.get(), |n| {
+                if &n.key >= k { Ordering::Less } else { Ordering::Greater }
+            }),
+            Bound::Excluded(k) => Self::splay_by(self.root.get(), |n| {
+                if &n.key > k { Ordering::Less } else { Ordering::Greater }
+            }),
+            Bound::Unbounded => Self::splay_by(self.root.get(), |_| Ordering::Less),
+        };
+        self.root.set(root);
+        let r = root?;
+        let rank = Self::size_of(r.as_ref().left);
+        if bound_contains_start(bound, &r.as_ref().key) {
+            Some((r, rank))
+        } else {
+            // every key in the left subtree is out of range as well
+            let mut p = r.as_ref().right?;
+            while let Some(l) = p.as_ref().left {
+                p = l;
+            }
+            Some((p, rank + 1))
+        }
+    }}
+
+    /// Splays the largest key within `bound`, returns it with its rank.
+    unsafe fn last_in(&self, bound: Bound<&K>) -> Option<(NonNull<Node<K, V>>, usize)> { unsafe {
+        let root = match bound {
+            Bound::Included(k) => Self::splay_by(self.root.get(), |n| {
+                if &n.key <= k { Ordering::Greater } else { Ordering::Less }
+            }),
+            Bound::Excluded(k) => Self::splay_by(self.root.get(), |n| {
+                if &n.key < k { Ordering::Greater } else { Ordering::Less }
+            }),
+            Bound::Unbounded => Self::splay_by(self.root.get(), |_| Ordering::Greater),
+        };
+        self.root.set(root);
+        let r = root?;
+        let rank = Self::size_of(r.as_ref().left);
+        if bound_contains_end(bound, &r.as_ref().key) {
+            Some((r, rank))
+        } else {
+            let mut p = r.as_ref().left?;
+            while let Some(q) = p.as_ref().right {
+                p = q;
+            }
+            Some((p, rank - 1))
+        }
+    }}
+
+    /// Plain lookup that leaves the shape untouched.
+    pub fn peek(&self, key: &K) -> Option<&V> {
+        let mut cur = self.root.get();
+        while let Some(p) = cur {
+            let b = unsafe { &*p.as_ptr() };
+            match key.cmp(&b.key) {
+                Ordering::Less => cur = b.left,
+                Ordering::Greater => cur = b.right,
+                Ordering::Equal => return Some(&b.val),
+            }
+        }
+        None
+    }
+
+    /// Doesn't splay, see [`Self::peek`].
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.peek(key).is_some()
+    }
+
+    unsafe fn splay(node: Link<K, V>, key: &K) -> Link<K, V> { unsafe {
+        Self::splay_by(node, |n| key.cmp(&n.key))
+    }}
+
+    /// Top-down splay. `cmp` tells where the target lies relative to a node
+    /// and is called exactly once per node on the search path, in order, so
+    /// it may carry state (e.g. a rank being descended).
+    ///
+    /// Nodes passed over are collected into a left tree (all smaller) and a
+    /// right tree (all greater), which become the children of the new root.
+    unsafe fn splay_by<F>(node: Link<K, V>, mut cmp: F) -> Link<K, V>
+    where
+        F: FnMut(&Node<K, V>) -> Ordering,
+    { unsafe {
+        let mut t = node?;
+        // top to bottom, each one hangs below the previous
+        let mut lefts = vec![];
+        let mut rights = vec![];
+        let mut c = cmp(t.as_ref());
+
+        loop {
+            match c {
+                Ordering::Equal => break,
                 Ordering::Less => {
-                    let root = Self::splay(nd.as_mut().right, key);
-                    nd.as_mut().set_right(root);
-                    nd.as_mut().left_rotate().or(node)
+                    let Some(l) = t.as_ref().left else { break };
+                    let c2 = cmp(l.as_ref());
+                    if c2 == Ordering::Less {
+                        // zig-zig: rotate right before linking
+                        Node::set_left(t, l.as_ref().right);
+                        Node::write_right(l, Some(t));
+                        t = l;
+                        let Some(ll) = t.as_ref().left else { break };
+                        rights.push(t);
+                        t = ll;
+                        c = cmp(t.as_ref());
+                    } else {
+                        rights.push(t);
+                        t = l;
+                        c = c2;
+                    }
+                }
+                Ordering::Greater => {
+                    let Some(r) = t.as_ref().right else { break };
+                    let c2 = cmp(r.as_ref());
+                    if c2 == Ordering::Greater {
+                        // zag-zag: rotate left before linking
+                        Node::set_right(t, r.as_ref().left);
+                        Node::write_left(r, Some(t));
+                        t = r;
+                        let Some(rr) = t.as_ref().right else { break };
+                        lefts.push(t);
+                        t = rr;
+                        c = cmp(t.as_ref());
+                    } else {
+                        lefts.push(t);
+                        t = r;
+                        c = c2;
+                    }
                 }
             }
-        } else {
-            None
         }
+
+        // reassemble, refreshing sizes bottom-up along both spines
+        let mut l = t.as_ref().left;
+        for p in lefts.into_iter().rev() {
+            Node::set_right(p, l);
+            l = Some(p);
+        }
+        let mut r = t.as_ref().right;
+        for p in rights.into_iter().rev() {
+            Node::set_left(p, r);
+            r = Some(p);
+        }
+        Node::write_left(t, l);
+        Node::set_right(t, r);
+        Some(t)
     }}
 
     pub fn remove(&mut self, key: &K) -> bool {
@@ -180,28 +367,204 @@ impl<K: Ord, V> SplayTreeMap<K, V> {
         }
 
         self.root.take().map(|x| unsafe {
-            let mut root = Box::from_raw(x.as_ptr());
-            let mut left = root.take_left();
+            let root = Box::from_raw(x.as_ptr());
+            // the maximum of the left subtree has no right child once splayed
+            let new_root = match Self::splay_by(root.left, |_| Ordering::Greater) {
+                Some(l) => {
+                    Node::set_right(l, root.right);
+                    Some(l)
+                }
+                None => root.right,
+            };
+            self.root.set(new_root);
+        });
+        debug_invariants!(self);
+        true
+    }
 
-            self.root = root.right;
+    /// Checks key order and subtree sizes.
+    pub fn validate(&self) -> Result<(), InvariantError> {
+        // iterative, a splay tree may degenerate into a path
+        let mut stack = vec![];
+        let mut cur = self.root.get();
+        let mut last: Option<&K> = None;
+
+        while cur.is_some() || !stack.is_empty() {
+            while let Some(p) = cur {
+                stack.push(p);
+                cur = unsafe { p.as_ref().left };
+            }
+            let Some(p) = stack.pop() else { break };
+            let p = unsafe { &*p.as_ptr() };
 
-            while let Some(mut l) = left {
-                if l.as_ref().right.is_none() {
-                    l.as_mut().set_right(root.right);
-                    self.root.replace(l);
+            if last.is_some_and(|k| k >= &p.key) {
+                return Err(InvariantError::Order);
+            }
+            let f = |u: Link<K, V>| u.map_or(0, |x| unsafe { x.as_ref().size });
+            let expected = 1 + f(p.left) + f(p.right);
+            if p.size != expected {
+                return Err(InvariantError::Size { expected, found: p.size });
+            }
+            last = Some(&p.key);
+            cur = p.right;
+        }
+        Ok(())
+    }
+}
+
+impl<'a, K: Ord, V> VacantEntry<'a, K, V> {
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    pub fn into_key(self) -> K {
+        self.key
+    }
+
+    /// The root is a neighbour of the key after splaying, so the new node
+    /// becomes the root and takes over one of its subtrees.
+    pub fn insert(self, val: V) -> &'a mut V {
+        let node = Node::new(self.key, val).wrap();
+        unsafe {
+            if let Some(mut r) = self.map.root.take() {
+                if r.as_ref().key < node.as_ref().key {
+                    Node::set_right(node, r.as_mut().take_right());
+                    Node::set_left(node, Some(r));
                 } else {
-                    left = l.as_mut().right_rotate();
+                    Node::set_left(node, r.as_mut().take_left());
+                    Node::set_right(node, Some(r));
                 }
             }
-        });
-        true
+            self.map.root.set(Some(node));
+            debug_invariants!(self.map);
+            &mut (*node.as_ptr()).val
+        }
+    }
+}
+
+fn bound_contains_start<K: Ord>(bound: Bound<&K>, key: &K) -> bool {
+    match bound {
+        Bound::Included(k) => key >= k,
+        Bound::Excluded(k) => key > k,
+        Bound::Unbounded => true,
+    }
+}
+
+fn bound_contains_end<K: Ord>(bound: Bound<&K>, key: &K) -> bool {
+    match bound {
+        Bound::Included(k) => key <= k,
+        Bound::Excluded(k) => key < k,
+        Bound::Unbounded => true,
+    }
+}
+
+/// Splaying iterator over a key range, see [`SplayTreeMap::range`].
+pub struct Range<'a, K, V> {
+    map: &'a SplayTreeMap<K, V>,
+    front: Link<K, V>,
+    back: Link<K, V>,
+    len: usize,
+}
+
+impl<'a, K: Ord, V> Iterator for Range<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.len == 0 {
+            return None;
+        }
+        let p = unsafe { &*self.front?.as_ptr() };
+        self.len -= 1;
+        if self.len > 0 {
+            self.front = unsafe { self.map.first_in(Bound::Excluded(&p.key)) }.map(|x| x.0);
+        }
+        Some((&p.key, &p.val))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<'a, K: Ord, V> DoubleEndedIterator for Range<'a, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.len == 0 {
+            return None;
+        }
+        let p = unsafe { &*self.back?.as_ptr() };
+        self.len -= 1;
+        if self.len > 0 {
+            self.back = unsafe { self.map.last_in(Bound::Excluded(&p.key)) }.map(|x| x.0);
+        }
+        Some((&p.key, &p.val))
+    }
+}
+
+impl<'a, K: Ord, V> ExactSizeIterator for Range<'a, K, V> {}
+
+impl<K, V> IntoIterator for SplayTreeMap<K, V> {
+    type Item = (K, V);
+    type IntoIter = binary_tree::IntoIter<Node<K, V>>;
+
+    fn into_iter(mut self) -> Self::IntoIter {
+        binary_tree::BinaryTree::into_iter(&mut self)
+    }
+}
+
+impl<'a, K: Ord, V> IntoIterator for &'a SplayTreeMap<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Range<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<K, V> binary_tree::BinaryTreeNode for Node<K, V> {
+    type Key = K;
+    type Value = V;
+
+    fn left(&self) -> &Option<NonNull<Self>> {
+        &self.left
+    }
+
+    fn mut_left(&mut self) -> &mut Option<NonNull<Self>> {
+        &mut self.left
+    }
+
+    fn right(&self) -> &Option<NonNull<Self>> {
+        &self.right
+    }
+
+    fn mut_right(&mut self) -> &mut Option<NonNull<Self>> {
+        &mut self.right
+    }
+
+    fn move_kv(self) -> (Self::Key, Self::Value) {
+        (self.key, self.val)
+    }
+
+    fn kv(&self) -> (&Self::Key, &Self::Value) {
+        (&self.key, &self.val)
+    }
+}
+
+impl<K, V> binary_tree::BinaryTree for SplayTreeMap<K, V> {
+    type Node = Node<K, V>;
+
+    fn root(&self) -> Option<NonNull<Self::Node>> {
+        self.root.get()
+    }
+
+    fn mut_root(&mut self) -> &mut Option<NonNull<Self::Node>> {
+        self.root.get_mut()
     }
 }
 
 impl<K, V> Drop for SplayTreeMap<K, V> {
     fn drop(&mut self) {
         let mut s = vec![];
-        if let Some(x) = self.root { s.push(x) }
+        if let Some(x) = self.root.get() { s.push(x) }
 
         while let Some(x) = s.pop() {
             let node = unsafe { Box::from_raw(x.as_ptr()) };
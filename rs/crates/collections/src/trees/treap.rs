@@ -1,36 +1,59 @@
 use super::binary_tree;
+use crate::invariant::InvariantError;
+use crate::misc::rng::{DefaultRng, default_rng};
 use ptr::NonNull;
-use std::{cmp::Ordering, io::SeekFrom, iter::Map, marker::PhantomData, ops::Add, ptr};
+use rand::{Rng, RngCore, SeedableRng};
+use std::{
+    cmp::Ordering,
+    hash::{DefaultHasher, Hash, Hasher},
+    io::SeekFrom,
+    iter::Map,
+    marker::PhantomData,
+    ptr,
+    rc::Rc,
+};
 
 type Edge<K, V> = Option<NonNull<Node<K, V>>>;
 
-impl_debug!(TreapMap);
+impl_debug!(TreapMap, R: RngCore);
+impl_entry!(R: RngCore = DefaultRng);
+
+pub struct VacantEntry<'a, K, V, R: RngCore = DefaultRng> {
+    key: K,
+    // search path from the root, with the side taken at each node
+    path: Vec<(NonNull<Node<K, V>>, bool)>,
+    map: &'a mut TreapMap<K, V, R>,
+}
 
-// todo: add size
 #[derive(Debug)]
 pub struct Node<K, V> {
     key: K,
     val: V,
-    pri: f32,
+    pub(crate) pri: f32,
+    size: usize,
     left: Edge<K, V>,
     right: Edge<K, V>,
 }
 
-pub struct TreapSet<T> {
-    map: TreapMap<T, ()>,
+pub struct TreapSet<T, R: RngCore = DefaultRng> {
+    map: TreapMap<T, (), R>,
 }
 
-pub struct TreapMap<K, V> {
+/// Priorities are drawn from `R`, [`default_rng`] unless one is passed to
+/// [`with_rng`](Self::with_rng).
+pub struct TreapMap<K, V, R: RngCore = DefaultRng> {
     root: Edge<K, V>,
+    rng: R,
     _marker: PhantomData<Box<(K, V)>>,
 }
 
 impl<K, V> Node<K, V> {
-    fn new(key: K, val: V) -> Self {
+    fn new(key: K, val: V, pri: f32) -> Self {
         Self {
             key,
             val,
-            pri: rand::random(),
+            pri,
+            size: 1,
             left: None,
             right: None,
         }
@@ -41,6 +64,26 @@ impl<K, V> Node<K, V> {
         NonNull::from(Box::leak(b))
     }
 
+    fn size_of(node: Edge<K, V>) -> usize {
+        node.map_or(0, |x| unsafe { x.as_ref().size })
+    }
+
+    fn update(&mut self) {
+        self.size = 1 + Self::size_of(self.left) + Self::size_of(self.right);
+    }
+
+    /// Frees the whole subtree.
+    fn free(node: Edge<K, V>) {
+        let mut s = vec![];
+        if let Some(x) = node { s.push(x) }
+
+        while let Some(x) = s.pop() {
+            let node = unsafe { Box::from_raw(x.as_ptr()) };
+            if let Some(u) = node.left { s.push(u) }
+            if let Some(u) = node.right { s.push(u) }
+        }
+    }
+
     // fn iter(&self) -> tree::InorderIter<>
 }
 
@@ -62,6 +105,14 @@ impl<T: Ord> TreapSet<T> {
             map: TreapMap::from(v.into_iter().map(|x| (x, ())).collect()),
         }
     }
+}
+
+impl<T: Ord, R: RngCore> TreapSet<T, R> {
+    pub fn with_rng(rng: R) -> Self {
+        Self {
+            map: TreapMap::with_rng(rng),
+        }
+    }
 
     pub fn insert(&mut self, elem: T) {
         self.map.insert(elem, ());
@@ -78,11 +129,52 @@ impl<T: Ord> TreapSet<T> {
     pub fn into_iter(mut self) -> binary_tree::IntoIter<Node<T, ()>> {
         (&mut self.map as &mut dyn binary_tree::BinaryTree<Node = Node<T, ()>>).into_iter()
     }
-}
 
-impl<T: Add<Output = T> + Ord + Unit + Clone> TreapSet<T> {
     pub fn remove(&mut self, elem: &T) -> bool {
-        self.map.remove(elem)
+        self.map.remove(elem).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Returns the `k`-th smallest element (0-indexed).
+    pub fn nth(&self, k: usize) -> Option<&T> {
+        self.map.nth(k).map(|(x, _)| x)
+    }
+
+    /// Returns the number of elements strictly less than `elem`.
+    pub fn rank(&self, elem: &T) -> usize {
+        self.map.rank(elem)
+    }
+
+    /// Splits into the `k` smallest elements and the rest.
+    pub fn split_at_rank(self, k: usize) -> (Self, Self)
+    where
+        R: SeedableRng,
+    {
+        let (a, b) = self.map.split_at_rank(k);
+        (Self { map: a }, Self { map: b })
+    }
+
+    pub fn union(self, other: Self) -> Self {
+        Self { map: self.map.union_with(other.map, |_, a, _| a) }
+    }
+
+    pub fn intersection(self, other: Self) -> Self {
+        Self { map: self.map.intersection_with(other.map, |_, a, _| a) }
+    }
+
+    pub fn difference(self, other: Self) -> Self {
+        Self { map: self.map.difference(other.map) }
+    }
+
+    pub fn symmetric_difference(self, other: Self) -> Self {
+        Self { map: self.map.symmetric_difference(other.map) }
     }
 }
 
@@ -93,13 +185,81 @@ impl<K, V> Default for TreapMap<K, V> {
 }
 
 impl<K, V> TreapMap<K, V> {
+    /// Creates an empty map drawing priorities from [`default_rng`].
     pub fn new() -> Self {
+        Self::with_rng(default_rng())
+    }
+}
+
+impl<K, V, R: RngCore> TreapMap<K, V, R> {
+    /// Creates an empty map drawing priorities from `rng`.
+    pub fn with_rng(rng: R) -> Self {
         Self {
             root: None,
+            rng,
             _marker: PhantomData,
         }
     }
 
+    pub fn len(&self) -> usize {
+        Node::size_of(self.root)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    /// Returns the `k`-th smallest entry (0-indexed).
+    pub fn nth(&self, mut k: usize) -> Option<(&K, &V)> {
+        let mut cur = self.root;
+        while let Some(p) = cur {
+            let b = unsafe { &*p.as_ptr() };
+            let l = Node::size_of(b.left);
+            match k.cmp(&l) {
+                Ordering::Equal => return Some((&b.key, &b.val)),
+                Ordering::Less => cur = b.left,
+                Ordering::Greater => {
+                    k -= l + 1;
+                    cur = b.right;
+                }
+            }
+        }
+        None
+    }
+
+    /// Splits into the `k` smallest entries and the rest, the first drawing
+    /// priorities from an rng seeded from this one.
+    pub fn split_at_rank(mut self, k: usize) -> (Self, Self)
+    where
+        R: SeedableRng,
+    {
+        let (a, b) = Self::split_rank_node(self.root.take(), k);
+        let mut lo = Self::with_rng(R::from_rng(&mut self.rng));
+        lo.root = a;
+        self.root = b;
+        (lo, self)
+    }
+
+    fn split_rank_node(mut node: Edge<K, V>, k: usize) -> (Edge<K, V>, Edge<K, V>) {
+        if let Some(nd) = &mut node {
+            let n = unsafe { nd.as_mut() };
+            let l = Node::size_of(n.left);
+            if l < k {
+                let (lt, ge) = Self::split_rank_node(n.right.take(), k - l - 1);
+                n.right = lt;
+                n.update();
+                (node, ge)
+            } else {
+                let (lt, ge) = Self::split_rank_node(n.left.take(), k);
+                n.left = ge;
+                n.update();
+                (lt, node)
+            }
+        } else {
+            (None, None)
+        }
+    }
+
     pub fn iter(&self) -> binary_tree::Iter<Node<K, V>> {
         (self as &dyn binary_tree::BinaryTree<Node = Node<K, V>>).iter()
     }
@@ -111,21 +271,25 @@ impl<K, V> TreapMap<K, V> {
 
 impl<K: Ord, V> TreapMap<K, V> {
     pub fn from(v: Vec<(K, V)>) -> Self {
+        let mut t = Self::new();
         if v.is_empty() {
-            return Self::new();
+            return t;
         }
 
         let mut stk: Vec<NonNull<Node<K, V>>> = vec![];
 
         for (k, v) in v {
-            let nd = Box::new(Node::new(k, v));
+            let nd = Box::new(Node::new(k, v, t.rng.random()));
             let pri = nd.pri;
             let mut node = NonNull::from(Box::leak(nd));
 
             unsafe {
-                while let Some(x) = stk.last() {
+                while let Some(&(mut x)) = stk.last() {
                     if x.as_ref().pri < pri {
-                        node.as_mut().left = stk.pop();
+                        // a popped node never gets new descendants
+                        stk.pop();
+                        x.as_mut().update();
+                        node.as_mut().left = Some(x);
                     } else {
                         break;
                     }
@@ -137,39 +301,29 @@ impl<K: Ord, V> TreapMap<K, V> {
             stk.push(node);
         }
 
-        Self {
-            root: stk.first().copied(),
-            _marker: PhantomData
+        for x in stk.iter_mut().rev() {
+            unsafe { x.as_mut().update() };
         }
-    }
 
-    fn split_node(mut node: Edge<K, V>, key: &K) -> (Edge<K, V>, Edge<K, V>) {
-        if let Some(nd) = &mut node {
-            let n = unsafe { nd.as_mut() };
-            if &n.key < key {
-                let (lt, ge) = Self::split_node(n.right.take(), key);
-                n.right = lt;
-                (node, ge)
-            } else {
-                let (lt, ge) = Self::split_node(n.left.take(), key);
-                n.left = ge;
-                (lt, node)
-            }
-        } else {
-            (None, None)
-        }
+        t.root = stk.first().copied();
+        debug_invariants!(t);
+        t
     }
+}
 
+impl<K: Ord, V, R: RngCore> TreapMap<K, V, R> {
     fn merge_node(mut node1: Edge<K, V>, mut node2: Edge<K, V>) -> Edge<K, V> {
         if let (Some(a), Some(b)) = (&mut node1, &mut node2) {
             unsafe {
                 if a.as_ref().pri > b.as_ref().pri {
                     let bow_a = a.as_mut();
                     bow_a.right = Self::merge_node(bow_a.right.take(), node2);
+                    bow_a.update();
                     node1
                 } else {
                     let bow_b = b.as_mut();
                     bow_b.left = Self::merge_node(node1, bow_b.left.take());
+                    bow_b.update();
                     node2
                 }
             }
@@ -178,6 +332,260 @@ impl<K: Ord, V> TreapMap<K, V> {
         }
     }
 
+    /// Checks key order and the heap order of priorities.
+    pub fn validate(&self) -> Result<(), InvariantError> {
+        Self::validate_node(self.root, None, None)
+    }
+
+    /// Removes the entry by replacing its node with the merge of its children.
+    pub fn remove(&mut self, key: &K) -> Option<(K, V)> {
+        let mut slot: *mut Edge<K, V> = &mut self.root;
+        let mut path = vec![];
+        unsafe {
+            while let Some(p) = *slot {
+                match p.as_ref().key.cmp(key) {
+                    Ordering::Less => {
+                        path.push(p);
+                        slot = &mut (*p.as_ptr()).right;
+                    }
+                    Ordering::Greater => {
+                        path.push(p);
+                        slot = &mut (*p.as_ptr()).left;
+                    }
+                    Ordering::Equal => {
+                        let nd = *Box::from_raw(p.as_ptr());
+                        *slot = Self::merge_node(nd.left, nd.right);
+                        for mut x in path {
+                            x.as_mut().size -= 1;
+                        }
+                        debug_invariants!(self);
+                        return Some((nd.key, nd.val));
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Merges two maps, `f(key, self_val, other_val)` resolves colliding keys.
+    pub fn union_with<F: FnMut(&K, V, V) -> V>(mut self, mut other: Self, mut f: F) -> Self {
+        let root = Self::union_node(self.root.take(), other.root.take(), &mut f);
+        self.with_root(root)
+    }
+
+    /// Keeps the keys present in both maps, combining their values with `f`.
+    pub fn intersection_with<F: FnMut(&K, V, V) -> V>(mut self, mut other: Self, mut f: F) -> Self {
+        let root = Self::intersection_node(self.root.take(), other.root.take(), &mut f);
+        self.with_root(root)
+    }
+
+    /// Keeps the entries whose keys are absent from `other`.
+    pub fn difference(mut self, mut other: Self) -> Self {
+        let root = Self::difference_node(self.root.take(), other.root.take());
+        self.with_root(root)
+    }
+
+    /// Keeps the entries whose keys are in exactly one of the maps.
+    pub fn symmetric_difference(mut self, mut other: Self) -> Self {
+        let root = Self::symmetric_difference_node(self.root.take(), other.root.take());
+        self.with_root(root)
+    }
+
+    fn with_root(mut self, root: Edge<K, V>) -> Self {
+        self.root = root;
+        debug_invariants!(self);
+        self
+    }
+
+    /// Splits into the keys less than, equal to and greater than `key`.
+    fn split3(node: Edge<K, V>, key: &K) -> (Edge<K, V>, Edge<K, V>, Edge<K, V>) {
+        let Some(mut p) = node else {
+            return (None, None, None);
+        };
+        let n = unsafe { p.as_mut() };
+        match n.key.cmp(key) {
+            Ordering::Less => {
+                let (l, m, r) = Self::split3(n.right.take(), key);
+                n.right = l;
+                n.update();
+                (node, m, r)
+            }
+            Ordering::Greater => {
+                let (l, m, r) = Self::split3(n.left.take(), key);
+                n.left = r;
+                n.update();
+                (l, m, node)
+            }
+            Ordering::Equal => {
+                let (l, r) = (n.left.take(), n.right.take());
+                n.update();
+                (l, node, r)
+            }
+        }
+    }
+
+    /// Reattaches the children of `p` and refreshes its size.
+    fn attach(mut p: NonNull<Node<K, V>>, left: Edge<K, V>, right: Edge<K, V>) -> Edge<K, V> {
+        let n = unsafe { p.as_mut() };
+        n.left = left;
+        n.right = right;
+        n.update();
+        Some(p)
+    }
+
+    /// Folds the detached node `other` into `p`, keeping the links and
+    /// priority of `p`. `p_first` tells which value came from `self`.
+    fn combine<F: FnMut(&K, V, V) -> V>(
+        p: NonNull<Node<K, V>>,
+        other: NonNull<Node<K, V>>,
+        p_first: bool,
+        f: &mut F,
+    ) -> NonNull<Node<K, V>> {
+        unsafe {
+            let b = *Box::from_raw(other.as_ptr());
+            let val = ptr::addr_of_mut!((*p.as_ptr()).val);
+            // `p` is detached from every tree, so a panicking `f` leaks it
+            // rather than dropping the value read out of it twice
+            let a = ptr::read(val);
+            let (x, y) = if p_first { (a, b.val) } else { (b.val, a) };
+            ptr::write(val, f(&p.as_ref().key, x, y));
+        }
+        p
+    }
+
+    fn union_node<F: FnMut(&K, V, V) -> V>(a: Edge<K, V>, b: Edge<K, V>, f: &mut F) -> Edge<K, V> {
+        let (pa, pb) = match (a, b) {
+            (None, x) | (x, None) => return x,
+            (Some(pa), Some(pb)) => (pa, pb),
+        };
+        unsafe {
+            if pa.as_ref().pri >= pb.as_ref().pri {
+                let (l, m, r) = Self::split3(b, &pa.as_ref().key);
+                let (al, ar) = ((*pa.as_ptr()).left.take(), (*pa.as_ptr()).right.take());
+                let p = m.map_or(pa, |m| Self::combine(pa, m, true, f));
+                let l = Self::union_node(al, l, f);
+                let r = Self::union_node(ar, r, f);
+                Self::attach(p, l, r)
+            } else {
+                let (l, m, r) = Self::split3(a, &pb.as_ref().key);
+                let (bl, br) = ((*pb.as_ptr()).left.take(), (*pb.as_ptr()).right.take());
+                let p = m.map_or(pb, |m| Self::combine(pb, m, false, f));
+                let l = Self::union_node(l, bl, f);
+                let r = Self::union_node(r, br, f);
+                Self::attach(p, l, r)
+            }
+        }
+    }
+
+    fn intersection_node<F: FnMut(&K, V, V) -> V>(a: Edge<K, V>, b: Edge<K, V>, f: &mut F) -> Edge<K, V> {
+        let (pa, pb) = match (a, b) {
+            (Some(pa), Some(pb)) => (pa, pb),
+            _ => {
+                Node::free(a);
+                Node::free(b);
+                return None;
+            }
+        };
+        unsafe {
+            let a_root = pa.as_ref().pri >= pb.as_ref().pri;
+            let (p, rest) = if a_root { (pa, b) } else { (pb, a) };
+            let (l, m, r) = Self::split3(rest, &p.as_ref().key);
+            let (pl, pr) = ((*p.as_ptr()).left.take(), (*p.as_ptr()).right.take());
+            let (l, r) = if a_root {
+                (Self::intersection_node(pl, l, f), Self::intersection_node(pr, r, f))
+            } else {
+                (Self::intersection_node(l, pl, f), Self::intersection_node(r, pr, f))
+            };
+            match m {
+                Some(m) => Self::attach(Self::combine(p, m, a_root, f), l, r),
+                None => {
+                    drop(Box::from_raw(p.as_ptr()));
+                    Self::merge_node(l, r)
+                }
+            }
+        }
+    }
+
+    fn difference_node(a: Edge<K, V>, b: Edge<K, V>) -> Edge<K, V> {
+        let (pa, pb) = match (a, b) {
+            (None, _) => {
+                Node::free(b);
+                return None;
+            }
+            (_, None) => return a,
+            (Some(pa), Some(pb)) => (pa, pb),
+        };
+        unsafe {
+            if pa.as_ref().pri >= pb.as_ref().pri {
+                let (l, m, r) = Self::split3(b, &pa.as_ref().key);
+                let (al, ar) = ((*pa.as_ptr()).left.take(), (*pa.as_ptr()).right.take());
+                let l = Self::difference_node(al, l);
+                let r = Self::difference_node(ar, r);
+                match m {
+                    Some(m) => {
+                        drop(Box::from_raw(m.as_ptr()));
+                        drop(Box::from_raw(pa.as_ptr()));
+                        Self::merge_node(l, r)
+                    }
+                    None => Self::attach(pa, l, r),
+                }
+            } else {
+                // `pb` only filters, every node of `a` stays in `l`, `m` or `r`
+                let (l, m, r) = Self::split3(a, &pb.as_ref().key);
+                let (bl, br) = ((*pb.as_ptr()).left.take(), (*pb.as_ptr()).right.take());
+                drop(Box::from_raw(pb.as_ptr()));
+                Node::free(m);
+                let l = Self::difference_node(l, bl);
+                let r = Self::difference_node(r, br);
+                Self::merge_node(l, r)
+            }
+        }
+    }
+
+    fn symmetric_difference_node(a: Edge<K, V>, b: Edge<K, V>) -> Edge<K, V> {
+        let (pa, pb) = match (a, b) {
+            (None, x) | (x, None) => return x,
+            (Some(pa), Some(pb)) => (pa, pb),
+        };
+        unsafe {
+            let a_root = pa.as_ref().pri >= pb.as_ref().pri;
+            let (p, rest) = if a_root { (pa, b) } else { (pb, a) };
+            let (l, m, r) = Self::split3(rest, &p.as_ref().key);
+            let (pl, pr) = ((*p.as_ptr()).left.take(), (*p.as_ptr()).right.take());
+            let l = Self::symmetric_difference_node(pl, l);
+            let r = Self::symmetric_difference_node(pr, r);
+            match m {
+                Some(m) => {
+                    drop(Box::from_raw(m.as_ptr()));
+                    drop(Box::from_raw(p.as_ptr()));
+                    Self::merge_node(l, r)
+                }
+                None => Self::attach(p, l, r),
+            }
+        }
+    }
+
+    fn validate_node<'a>(node: Edge<K, V>, lo: Option<&'a K>, hi: Option<&'a K>) -> Result<(), InvariantError> {
+        let Some(p) = node else { return Ok(()) };
+        let key: &'a K = unsafe { &(*p.as_ptr()).key };
+        let p = unsafe { p.as_ref() };
+
+        if lo.is_some_and(|k| k >= key) || hi.is_some_and(|k| k <= key) {
+            return Err(InvariantError::Order);
+        }
+        for c in [p.left, p.right].into_iter().flatten() {
+            if unsafe { c.as_ref().pri } > p.pri {
+                return Err(InvariantError::Priority);
+            }
+        }
+        let expected = 1 + Node::size_of(p.left) + Node::size_of(p.right);
+        if p.size != expected {
+            return Err(InvariantError::Size { expected, found: p.size });
+        }
+        Self::validate_node(p.left, lo, Some(key))?;
+        Self::validate_node(p.right, Some(key), hi)
+    }
+
     pub fn get<'a>(&'a self, key: &K) -> Option<&'a V> {
         let mut p = self.root.as_ref();
         while let Some(r) = p {
@@ -192,49 +600,118 @@ impl<K: Ord, V> TreapMap<K, V> {
         None
     }
 
-    pub fn insert(&mut self, key: K, val: V) -> bool {
-        if self.get(&key).is_some() {
-            return false;
+    /// Returns the number of keys strictly less than `key`.
+    pub fn rank(&self, key: &K) -> usize {
+        let mut cur = self.root;
+        let mut r = 0;
+        while let Some(p) = cur {
+            let b = unsafe { &*p.as_ptr() };
+            if &b.key < key {
+                r += Node::size_of(b.left) + 1;
+                cur = b.right;
+            } else {
+                cur = b.left;
+            }
         }
-        let (mut lt, ge) = Self::split_node(self.root.take(), &key);
-        lt = Self::merge_node(lt, Some(Node::new(key, val).wrap()));
-        self.root = Self::merge_node(lt, ge);
-        true
+        r
     }
-}
 
-impl<K, V> Drop for TreapMap<K, V> {
-    fn drop(&mut self) {
-        let mut s = vec![];
-        if let Some(x) = self.root { s.push(x) }
+    /// Inserts or overwrites, returns `false` if the key was already present.
+    pub fn insert(&mut self, key: K, val: V) -> bool {
+        match self.entry(key) {
+            Entry::Occupied(mut e) => {
+                e.insert(val);
+                false
+            }
+            Entry::Vacant(e) => {
+                e.insert(val);
+                true
+            }
+        }
+    }
 
-        while let Some(x) = s.pop() {
-            let node = unsafe { Box::from_raw(x.as_ptr()) };
-            if let Some(u) = node.left { s.push(u) }
-            if let Some(u) = node.right { s.push(u) }
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V, R> {
+        let mut path = vec![];
+        let mut cur = self.root;
+        while let Some(p) = cur {
+            let b = unsafe { p.as_ref() };
+            match b.key.cmp(&key) {
+                Ordering::Equal => return Entry::Occupied(OccupiedEntry::new(p)),
+                Ordering::Greater => {
+                    path.push((p, true));
+                    cur = b.left;
+                }
+                Ordering::Less => {
+                    path.push((p, false));
+                    cur = b.right;
+                }
+            }
         }
+        Entry::Vacant(VacantEntry {
+            key,
+            path,
+            map: self,
+        })
     }
 }
 
-pub trait Unit {
-    fn unit() -> Self;
-}
+impl<'a, K: Ord, V, R: RngCore> VacantEntry<'a, K, V, R> {
+    pub fn key(&self) -> &K {
+        &self.key
+    }
 
-impl<K: Add<Output = K> + Ord + Unit + Clone, V> TreapMap<K, V> {
-    pub fn remove(&mut self, key: &K) -> bool {
-        if self.get(key).is_none() {
-            return false;
+    pub fn into_key(self) -> K {
+        self.key
+    }
+
+    pub fn insert(self, val: V) -> &'a mut V {
+        let mut node = Node::new(self.key, val, self.map.rng.random()).wrap();
+        let path = self.path;
+        unsafe {
+            let pri = node.as_ref().pri;
+            // the new node replaces the first node on the path with a lower
+            // priority, the rest of the path is exactly the split path
+            let i = path
+                .iter()
+                .position(|(p, _)| p.as_ref().pri < pri)
+                .unwrap_or(path.len());
+
+            // both from the raw pointer, a second `as_mut` would invalidate the first
+            let mut lt: *mut Edge<K, V> = ptr::addr_of_mut!((*node.as_ptr()).left);
+            let mut ge: *mut Edge<K, V> = ptr::addr_of_mut!((*node.as_ptr()).right);
+            for &(p, is_left) in &path[i..] {
+                if is_left {
+                    *ge = Some(p);
+                    ge = &mut (*p.as_ptr()).left;
+                } else {
+                    *lt = Some(p);
+                    lt = &mut (*p.as_ptr()).right;
+                }
+            }
+            *lt = None;
+            *ge = None;
+            for &(mut p, _) in path[i..].iter().rev() {
+                p.as_mut().update();
+            }
+            node.as_mut().update();
+            for &(mut p, _) in &path[..i] {
+                p.as_mut().size += 1;
+            }
+
+            match i.checked_sub(1).map(|j| path[j]) {
+                Some((mut p, true)) => p.as_mut().left = Some(node),
+                Some((mut p, false)) => p.as_mut().right = Some(node),
+                None => self.map.root = Some(node),
+            }
+            debug_invariants!(self.map);
+            &mut (*node.as_ptr()).val
         }
-        let (l, r) = Self::split_node(self.root.take(), key);
-        let (_, r) = Self::split_node(r, &key.clone().add(K::unit()));
-        self.root = Self::merge_node(l, r);
-        true
     }
 }
 
-impl Unit for i32 {
-    fn unit() -> Self {
-        1
+impl<K, V, R: RngCore> Drop for TreapMap<K, V, R> {
+    fn drop(&mut self) {
+        Node::free(self.root.take());
     }
 }
 
@@ -266,7 +743,7 @@ impl<K, V> binary_tree::BinaryTreeNode for Node<K, V> {
     }
 }
 
-impl<K, V> binary_tree::BinaryTree for TreapMap<K, V> {
+impl<K, V, R: RngCore> binary_tree::BinaryTree for TreapMap<K, V, R> {
     type Node = Node<K, V>;
     fn root(&self) -> Option<NonNull<Self::Node>> {
         self.root
@@ -275,3 +752,271 @@ impl<K, V> binary_tree::BinaryTree for TreapMap<K, V> {
         &mut self.root
     }
 }
+
+/// Persistent TreapMap, every update returns a new version sharing
+/// structure with the old one.
+///
+/// Priorities are hashed from the keys, so equal key sets always produce
+/// the same shape.
+#[derive(Debug)]
+pub struct PstTreapMap<K, V> {
+    root: PstEdge<K, V>,
+    len: usize,
+}
+
+#[derive(Debug, Clone)]
+struct PstNode<K, V> {
+    key: K,
+    val: V,
+    pri: u64,
+    left: PstEdge<K, V>,
+    right: PstEdge<K, V>,
+}
+
+type PstEdge<K, V> = Option<Rc<PstNode<K, V>>>;
+
+impl<K, V> Clone for PstTreapMap<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            root: self.root.clone(),
+            len: self.len,
+        }
+    }
+}
+
+impl<K, V> Default for PstTreapMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V> PstTreapMap<K, V> {
+    pub fn new() -> Self {
+        Self { root: None, len: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn iter(&self) -> PstIter<'_, K, V> {
+        let mut it = PstIter { stack: vec![] };
+        it.push_left(self.root.as_deref());
+        it
+    }
+}
+
+impl<K: Ord + Hash + Clone, V: Clone> PstTreapMap<K, V> {
+    fn priority(key: &K) -> u64 {
+        let mut h = DefaultHasher::new();
+        key.hash(&mut h);
+        h.finish()
+    }
+
+    /// Heap order on `(pri, key)`, so hash collisions still give a total order.
+    fn above(pri: u64, key: &K, node: &PstNode<K, V>) -> bool {
+        (pri, key) > (node.pri, &node.key)
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        let mut p = self.root.as_deref();
+        while let Some(b) = p {
+            match b.key.cmp(key) {
+                Ordering::Less => p = b.right.as_deref(),
+                Ordering::Greater => p = b.left.as_deref(),
+                Ordering::Equal => return Some(&b.val),
+            }
+        }
+        None
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Returns a new version with `key` set to `val`.
+    pub fn insert(&self, key: K, val: V) -> Self {
+        let pri = Self::priority(&key);
+        let (root, added) = Self::insert_node(&self.root, key, val, pri);
+        let t = Self {
+            root: Some(root),
+            len: self.len + added as usize,
+        };
+        debug_invariants!(t);
+        t
+    }
+
+    /// Returns a new version without `key`, sharing the whole tree if absent.
+    pub fn remove(&self, key: &K) -> Self {
+        let t = match Self::remove_node(&self.root, key) {
+            Some(root) => Self {
+                root,
+                len: self.len - 1,
+            },
+            None => self.clone(),
+        };
+        debug_invariants!(t);
+        t
+    }
+
+    fn insert_node(node: &PstEdge<K, V>, key: K, val: V, pri: u64) -> (Rc<PstNode<K, V>>, bool) {
+        let Some(n) = node else {
+            let leaf = PstNode {
+                key,
+                val,
+                pri,
+                left: None,
+                right: None,
+            };
+            return (Rc::new(leaf), true);
+        };
+
+        if Self::above(pri, &key, n) {
+            // the key can't be below a node with a lower priority
+            let (left, right) = Self::split_node(node, &key);
+            let nd = PstNode {
+                key,
+                val,
+                pri,
+                left,
+                right,
+            };
+            return (Rc::new(nd), true);
+        }
+
+        match n.key.cmp(&key) {
+            Ordering::Equal => {
+                let nd = PstNode {
+                    key,
+                    val,
+                    pri,
+                    left: n.left.clone(),
+                    right: n.right.clone(),
+                };
+                (Rc::new(nd), false)
+            }
+            Ordering::Greater => {
+                let (left, added) = Self::insert_node(&n.left, key, val, pri);
+                let mut nd = PstNode::clone(n);
+                nd.left = Some(left);
+                (Rc::new(nd), added)
+            }
+            Ordering::Less => {
+                let (right, added) = Self::insert_node(&n.right, key, val, pri);
+                let mut nd = PstNode::clone(n);
+                nd.right = Some(right);
+                (Rc::new(nd), added)
+            }
+        }
+    }
+
+    /// Splits by a key that is not in the tree, copying only the split path.
+    fn split_node(node: &PstEdge<K, V>, key: &K) -> (PstEdge<K, V>, PstEdge<K, V>) {
+        let Some(n) = node else {
+            return (None, None);
+        };
+        let mut nd = PstNode::clone(n);
+        if &n.key < key {
+            let (lt, gt) = Self::split_node(&n.right, key);
+            nd.right = lt;
+            (Some(Rc::new(nd)), gt)
+        } else {
+            let (lt, gt) = Self::split_node(&n.left, key);
+            nd.left = gt;
+            (lt, Some(Rc::new(nd)))
+        }
+    }
+
+    fn merge_node(a: &PstEdge<K, V>, b: &PstEdge<K, V>) -> PstEdge<K, V> {
+        match (a, b) {
+            (None, x) | (x, None) => x.clone(),
+            (Some(x), Some(y)) => {
+                if Self::above(x.pri, &x.key, y) {
+                    let mut nd = PstNode::clone(x);
+                    nd.right = Self::merge_node(&x.right, b);
+                    Some(Rc::new(nd))
+                } else {
+                    let mut nd = PstNode::clone(y);
+                    nd.left = Self::merge_node(a, &y.left);
+                    Some(Rc::new(nd))
+                }
+            }
+        }
+    }
+
+    /// Returns `None` if the key is absent, so the caller can share the old tree.
+    fn remove_node(node: &PstEdge<K, V>, key: &K) -> Option<PstEdge<K, V>> {
+        let n = node.as_ref()?;
+        match n.key.cmp(key) {
+            Ordering::Equal => Some(Self::merge_node(&n.left, &n.right)),
+            Ordering::Greater => {
+                let left = Self::remove_node(&n.left, key)?;
+                let mut nd = PstNode::clone(n);
+                nd.left = left;
+                Some(Some(Rc::new(nd)))
+            }
+            Ordering::Less => {
+                let right = Self::remove_node(&n.right, key)?;
+                let mut nd = PstNode::clone(n);
+                nd.right = right;
+                Some(Some(Rc::new(nd)))
+            }
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), InvariantError> {
+        let mut found = 0;
+        let mut stack = vec![(self.root.as_deref(), None, None)];
+        while let Some((node, lo, hi)) = stack.pop() {
+            let Some(n) = node else { continue };
+            found += 1;
+            if lo.is_some_and(|lo| lo >= &n.key) || hi.is_some_and(|hi| hi <= &n.key) {
+                return Err(InvariantError::Order);
+            }
+            if n.pri != Self::priority(&n.key) {
+                return Err(InvariantError::Priority);
+            }
+            for c in [&n.left, &n.right].into_iter().flatten() {
+                if Self::above(c.pri, &c.key, n) {
+                    return Err(InvariantError::Priority);
+                }
+            }
+            stack.push((n.left.as_deref(), lo, Some(&n.key)));
+            stack.push((n.right.as_deref(), Some(&n.key), hi));
+        }
+        if found != self.len {
+            return Err(InvariantError::Size {
+                expected: found,
+                found: self.len,
+            });
+        }
+        Ok(())
+    }
+}
+
+pub struct PstIter<'a, K, V> {
+    stack: Vec<&'a PstNode<K, V>>,
+}
+
+impl<'a, K, V> PstIter<'a, K, V> {
+    fn push_left(&mut self, mut node: Option<&'a PstNode<K, V>>) {
+        while let Some(n) = node {
+            self.stack.push(n);
+            node = n.left.as_deref();
+        }
+    }
+}
+
+impl<'a, K, V> Iterator for PstIter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let n = self.stack.pop()?;
+        self.push_left(n.right.as_deref());
+        Some((&n.key, &n.val))
+    }
+}
@@ -0,0 +1,409 @@
+use super::monoid::Monoid;
+use crate::misc::rng::{DefaultRng, default_rng};
+use rand::{Rng, SeedableRng};
+use std::{
+    fmt, mem,
+    ops::{Bound, RangeBounds},
+};
+
+type Link<T, M> = Option<Box<Node<T, M>>>;
+
+/// How the elements of a `TreapSeq` are aggregated over ranges.
+///
+/// Every `Monoid` summarizes its own element type, `()` summarizes nothing.
+pub trait Summary<T> {
+    type S: Clone;
+
+    fn identity() -> Self::S;
+    fn op(a: &Self::S, b: &Self::S) -> Self::S;
+    fn of(x: &T) -> Self::S;
+}
+
+impl<M: Monoid> Summary<M::T> for M {
+    type S = M::T;
+
+    fn identity() -> Self::S {
+        M::identity()
+    }
+
+    fn op(a: &Self::S, b: &Self::S) -> Self::S {
+        M::op(a, b)
+    }
+
+    fn of(x: &M::T) -> Self::S {
+        x.clone()
+    }
+}
+
+impl<T> Summary<T> for () {
+    type S = ();
+
+    fn identity() {}
+    fn op(_: &(), _: &()) {}
+    fn of(_: &T) {}
+}
+
+struct Node<T, M: Summary<T>> {
+    val: T,
+    pri: f32,
+    size: usize,
+    // the children are still to be reversed, this node is up to date
+    rev: bool,
+    sum: M::S,
+    // aggregate of the subtree read backwards, kept for non-commutative ops
+    rev_sum: M::S,
+    left: Link<T, M>,
+    right: Link<T, M>,
+}
+
+/// An implicit treap: a sequence keyed by position, with O(log n) insertion,
+/// removal, splitting, concatenation, reversal and range aggregates.
+///
+/// Like `Vec`, `insert_at`, `remove_at`, `set` and `split_at` panic with
+/// "index out of range" on a position past the end; only `get` returns
+/// `None` instead.
+pub struct TreapSeq<T, M: Summary<T> = ()> {
+    root: Link<T, M>,
+    rng: DefaultRng,
+}
+
+impl<T, M: Summary<T>> Node<T, M> {
+    fn new(val: T, pri: f32) -> Box<Self> {
+        let sum = M::of(&val);
+        Box::new(Self {
+            val,
+            pri,
+            size: 1,
+            rev: false,
+            rev_sum: sum.clone(),
+            sum,
+            left: None,
+            right: None,
+        })
+    }
+
+    fn size(node: &Link<T, M>) -> usize {
+        node.as_ref().map_or(0, |x| x.size)
+    }
+
+    fn sum(node: &Link<T, M>) -> M::S {
+        node.as_ref().map_or_else(M::identity, |x| x.sum.clone())
+    }
+
+    fn rev_sum(node: &Link<T, M>) -> M::S {
+        node.as_ref()
+            .map_or_else(M::identity, |x| x.rev_sum.clone())
+    }
+
+    fn reverse(&mut self) {
+        mem::swap(&mut self.left, &mut self.right);
+        mem::swap(&mut self.sum, &mut self.rev_sum);
+        self.rev ^= true;
+    }
+
+    fn push_down(&mut self) {
+        if self.rev {
+            for c in [&mut self.left, &mut self.right].into_iter().flatten() {
+                c.reverse();
+            }
+            self.rev = false;
+        }
+    }
+
+    fn update(&mut self) {
+        let val = M::of(&self.val);
+        self.size = 1 + Self::size(&self.left) + Self::size(&self.right);
+        self.sum = M::op(
+            &M::op(&Self::sum(&self.left), &val),
+            &Self::sum(&self.right),
+        );
+        self.rev_sum = M::op(
+            &M::op(&Self::rev_sum(&self.right), &val),
+            &Self::rev_sum(&self.left),
+        );
+    }
+}
+
+impl<T, M: Summary<T>> Default for TreapSeq<T, M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, M: Summary<T>> TreapSeq<T, M> {
+    /// Creates an empty sequence drawing priorities from [`default_rng`].
+    pub fn new() -> Self {
+        Self::with_rng(default_rng())
+    }
+
+    /// Creates an empty sequence drawing priorities from `rng`.
+    pub fn with_rng(rng: DefaultRng) -> Self {
+        Self {
+            root: None,
+            rng,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        Node::size(&self.root)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    /// Splits into the first `k` elements and the rest.
+    fn split_node(node: Link<T, M>, k: usize) -> (Link<T, M>, Link<T, M>) {
+        if let Some(mut nd) = node {
+            nd.push_down();
+            let ls = Node::size(&nd.left);
+            if k <= ls {
+                let (a, b) = Self::split_node(nd.left.take(), k);
+                nd.left = b;
+                nd.update();
+                (a, Some(nd))
+            } else {
+                let (a, b) = Self::split_node(nd.right.take(), k - ls - 1);
+                nd.right = a;
+                nd.update();
+                (Some(nd), b)
+            }
+        } else {
+            (None, None)
+        }
+    }
+
+    fn merge_node(node1: Link<T, M>, node2: Link<T, M>) -> Link<T, M> {
+        match (node1, node2) {
+            (Some(mut a), Some(mut b)) => {
+                if a.pri > b.pri {
+                    a.push_down();
+                    a.right = Self::merge_node(a.right.take(), Some(b));
+                    a.update();
+                    Some(a)
+                } else {
+                    b.push_down();
+                    b.left = Self::merge_node(Some(a), b.left.take());
+                    b.update();
+                    Some(b)
+                }
+            }
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// Cuts `range` out of the sequence, runs `f` on it and puts it back.
+    fn with_range<U, R: RangeBounds<usize>>(
+        &mut self,
+        range: R,
+        f: impl FnOnce(&mut Link<T, M>) -> U,
+    ) -> U {
+        let (l, r) = self.bounds(range);
+        let (a, rest) = Self::split_node(self.root.take(), l);
+        let (mut b, c) = Self::split_node(rest, r - l);
+        let res = f(&mut b);
+        self.root = Self::merge_node(Self::merge_node(a, b), c);
+        res
+    }
+
+    fn bounds<R: RangeBounds<usize>>(&self, range: R) -> (usize, usize) {
+        let l = match range.start_bound() {
+            Bound::Included(&x) => x,
+            Bound::Excluded(&x) => x + 1,
+            Bound::Unbounded => 0,
+        };
+        let r = match range.end_bound() {
+            Bound::Included(&x) => x + 1,
+            Bound::Excluded(&x) => x,
+            Bound::Unbounded => self.len(),
+        };
+        assert!(
+            l <= r && r <= self.len(),
+            "range {}..{} out of bounds",
+            l,
+            r
+        );
+        (l, r)
+    }
+
+    pub fn push_back(&mut self, val: T) {
+        let node = Node::new(val, self.rng.random());
+        self.root = Self::merge_node(self.root.take(), Some(node));
+    }
+
+    /// Inserts `val` at position `i`, shifting the rest to the right.
+    ///
+    /// Panics if `i > len`.
+    pub fn insert_at(&mut self, i: usize, val: T) {
+        assert!(i <= self.len(), "index out of range");
+        let (a, b) = Self::split_node(self.root.take(), i);
+        let node = Node::new(val, self.rng.random());
+        self.root = Self::merge_node(Self::merge_node(a, Some(node)), b);
+    }
+
+    /// Removes and returns the element at position `i`, shifting the rest to
+    /// the left.
+    ///
+    /// Panics if `i >= len`.
+    pub fn remove_at(&mut self, i: usize) -> T {
+        assert!(i < self.len(), "index out of range");
+        let (a, rest) = Self::split_node(self.root.take(), i);
+        let (b, c) = Self::split_node(rest, 1);
+        self.root = Self::merge_node(a, c);
+        b.expect("must exist").val
+    }
+
+    /// Splits into the first `i` elements and the rest.
+    ///
+    /// Panics if `i > len`.
+    pub fn split_at(mut self, i: usize) -> (Self, Self) {
+        assert!(i <= self.len(), "index out of range");
+        let (a, b) = Self::split_node(self.root.take(), i);
+        let rng = DefaultRng::from_rng(&mut self.rng);
+        (Self { root: a, rng }, Self { root: b, rng: self.rng })
+    }
+
+    pub fn concat(mut self, mut other: Self) -> Self {
+        Self {
+            root: Self::merge_node(self.root.take(), other.root.take()),
+            rng: self.rng,
+        }
+    }
+
+    pub fn reverse<R: RangeBounds<usize>>(&mut self, range: R) {
+        self.with_range(range, |x| {
+            if let Some(x) = x {
+                x.reverse();
+            }
+        })
+    }
+
+    /// Aggregates `range` in order.
+    pub fn query<R: RangeBounds<usize>>(&self, range: R) -> M::S {
+        let (l, r) = self.bounds(range);
+        Self::query_node(&self.root, l, r, false)
+    }
+
+    /// Aggregates positions `l..r` of the subtree, which reads backwards
+    /// if `rev` is set by a pending reversal above it.
+    fn query_node(node: &Link<T, M>, l: usize, r: usize, rev: bool) -> M::S {
+        let Some(p) = node else {
+            return M::identity();
+        };
+        if l >= r {
+            return M::identity();
+        }
+        if l == 0 && r >= p.size {
+            return if rev { p.rev_sum.clone() } else { p.sum.clone() };
+        }
+
+        let (a, b) = if rev {
+            (&p.right, &p.left)
+        } else {
+            (&p.left, &p.right)
+        };
+        let rev = rev ^ p.rev;
+        let ls = Node::size(a);
+        let mut acc = Self::query_node(a, l, r.min(ls), rev);
+        if l <= ls && ls < r {
+            acc = M::op(&acc, &M::of(&p.val));
+        }
+        let rest = Self::query_node(b, l.saturating_sub(ls + 1), r.saturating_sub(ls + 1), rev);
+        M::op(&acc, &rest)
+    }
+
+    pub fn get(&self, mut i: usize) -> Option<&T> {
+        let mut cur = self.root.as_deref();
+        // pending reversal of the current subtree
+        let mut rev = false;
+
+        while let Some(p) = cur {
+            let (l, r) = if rev {
+                (&p.right, &p.left)
+            } else {
+                (&p.left, &p.right)
+            };
+            let ls = Node::size(l);
+            rev ^= p.rev;
+            if i < ls {
+                cur = l.as_deref();
+            } else if i == ls {
+                return Some(&p.val);
+            } else {
+                i -= ls + 1;
+                cur = r.as_deref();
+            }
+        }
+        None
+    }
+
+    /// Replaces the element at `i`, returning the old one.
+    /// Replaces the element at position `i`, returning the old one.
+    ///
+    /// Panics if `i >= len`.
+    pub fn set(&mut self, i: usize, val: T) -> T {
+        assert!(i < self.len(), "index out of range");
+        self.with_range(i..i + 1, |x| {
+            let x = x.as_mut().expect("must exist");
+            let old = mem::replace(&mut x.val, val);
+            x.update();
+            old
+        })
+    }
+
+    pub fn iter(&self) -> Iter<'_, T, M> {
+        let mut it = Iter { stack: vec![] };
+        it.push_left(self.root.as_deref(), false);
+        it
+    }
+}
+
+impl<T, M: Summary<T>> FromIterator<T> for TreapSeq<T, M> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut s = Self::new();
+        for x in iter {
+            s.push_back(x);
+        }
+        s
+    }
+}
+
+impl<T: fmt::Debug, M: Summary<T>> fmt::Debug for TreapSeq<T, M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+pub struct Iter<'a, T, M: Summary<T>> {
+    // nodes with the pending reversal of their subtree
+    stack: Vec<(&'a Node<T, M>, bool)>,
+}
+
+impl<'a, T, M: Summary<T>> Iter<'a, T, M> {
+    fn push_left(&mut self, mut cur: Option<&'a Node<T, M>>, mut rev: bool) {
+        while let Some(p) = cur {
+            self.stack.push((p, rev));
+            cur = if rev {
+                p.right.as_deref()
+            } else {
+                p.left.as_deref()
+            };
+            rev ^= p.rev;
+        }
+    }
+}
+
+impl<'a, T, M: Summary<T>> Iterator for Iter<'a, T, M> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (p, rev) = self.stack.pop()?;
+        let r = if rev {
+            p.left.as_deref()
+        } else {
+            p.right.as_deref()
+        };
+        self.push_left(r, rev ^ p.rev);
+        Some(&p.val)
+    }
+}